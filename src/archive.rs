@@ -0,0 +1,122 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{BufReader, Read, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+
+use crate::{
+    generate_hash,
+    manifest::{Manifest, ZoneHashes},
+    source::Representation,
+};
+
+pub type Archive = tar::Archive<zstd::Decoder<'static, BufReader<File>>>;
+
+/// Open a snapshot archive for streaming its members.
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Archive, anyhow::Error> {
+    let path = path.as_ref();
+    let f = File::open(path).with_context(|| format!("{path:?}"))?;
+    let decoder = zstd::Decoder::new(f).with_context(|| format!("decoding {path:?}"))?;
+
+    Ok(tar::Archive::new(decoder))
+}
+
+/// Read a single member out of a snapshot archive, if it is present.
+pub fn read_member<P: AsRef<Path>>(path: P, name: &str) -> Result<Option<Vec<u8>>, anyhow::Error> {
+    let path = path.as_ref();
+    let mut archive = open(path)?;
+    for entry in archive
+        .entries()
+        .with_context(|| format!("reading {path:?}"))?
+    {
+        let mut entry = entry.with_context(|| format!("reading {path:?}"))?;
+        if entry.path()?.to_str() != Some(name) {
+            continue;
+        }
+        let mut buf = Vec::with_capacity(entry.size() as usize);
+        entry
+            .read_to_end(&mut buf)
+            .with_context(|| format!("reading {name} from {path:?}"))?;
+        return Ok(Some(buf));
+    }
+
+    Ok(None)
+}
+
+/// Stream a single member out of a snapshot archive into `out`, without
+/// holding it in memory. Returns whether the member was found.
+pub fn copy_member<P: AsRef<Path>, W: Write>(
+    path: P,
+    name: &str,
+    mut out: W,
+) -> Result<bool, anyhow::Error> {
+    let path = path.as_ref();
+    let mut archive = open(path)?;
+    for entry in archive
+        .entries()
+        .with_context(|| format!("reading {path:?}"))?
+    {
+        let mut entry = entry.with_context(|| format!("reading {path:?}"))?;
+        if entry.path()?.to_str() != Some(name) {
+            continue;
+        }
+        std::io::copy(&mut entry, &mut out)
+            .with_context(|| format!("reading {name} from {path:?}"))?;
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+/// Read every member of a snapshot archive into memory, keyed by name.
+pub fn read_members<P: AsRef<Path>>(path: P) -> Result<BTreeMap<String, Vec<u8>>, anyhow::Error> {
+    let path = path.as_ref();
+    let mut archive = open(path)?;
+    let mut members = BTreeMap::new();
+    for entry in archive
+        .entries()
+        .with_context(|| format!("reading {path:?}"))?
+    {
+        let mut entry = entry.with_context(|| format!("reading {path:?}"))?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut buf = Vec::with_capacity(entry.size() as usize);
+        entry
+            .read_to_end(&mut buf)
+            .with_context(|| format!("reading {name} from {path:?}"))?;
+        members.insert(name, buf);
+    }
+
+    Ok(members)
+}
+
+/// The SHA-256 of every zone member in a snapshot archive, grouped by zone.
+///
+/// This comes from the manifest when there is one, archives written before
+/// manifests existed are decoded and hashed instead.
+pub fn zone_hashes<P: AsRef<Path>>(path: P) -> Result<ZoneHashes, anyhow::Error> {
+    let path = path.as_ref();
+    if let Some(manifest) = Manifest::from_archive(path)? {
+        return Ok(manifest.zone_hashes());
+    }
+
+    let mut hashes = ZoneHashes::new();
+    let mut archive = open(path)?;
+    for entry in archive
+        .entries()
+        .with_context(|| format!("reading {path:?}"))?
+    {
+        let entry = entry.with_context(|| format!("reading {path:?}"))?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let zone = match Representation::from_member_name(&name) {
+            Some((zone, _)) => zone.to_string(),
+            None => continue,
+        };
+        let hash = generate_hash(entry).with_context(|| format!("reading {name} from {path:?}"))?;
+        hashes.entry(zone).or_default().insert(name, hash);
+    }
+
+    Ok(hashes)
+}
@@ -0,0 +1,158 @@
+use std::{
+    fs, io,
+    mem::MaybeUninit,
+    path::{Path, PathBuf},
+    ptr,
+    sync::Mutex,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Context;
+use slog::{info, warn};
+
+use crate::Ctx;
+
+/// File name prefix of every temporary file this tool creates in `outdir`.
+pub const TEMP_PREFIX: &str = ".zonecfg-backup.tmp";
+
+/// Temporary files older than this are assumed to be left over from an
+/// interrupted run.
+pub const STALE_TEMP_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Temporary files that should be removed if we are interrupted.
+static IN_PROGRESS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Process groups of running hooks, passed on any signal we exit for.
+static CHILD_GROUPS: Mutex<Vec<u32>> = Mutex::new(Vec::new());
+
+/// Keeps a temporary file registered for removal on SIGINT/SIGTERM until
+/// dropped.
+#[derive(Debug)]
+pub struct TempGuard {
+    path: PathBuf,
+}
+
+impl TempGuard {
+    pub fn new(path: &Path) -> Self {
+        let path = path.to_path_buf();
+        IN_PROGRESS.lock().unwrap().push(path.clone());
+        Self { path }
+    }
+}
+
+impl Drop for TempGuard {
+    fn drop(&mut self) {
+        let mut paths = IN_PROGRESS.lock().unwrap();
+        if let Some(i) = paths.iter().position(|p| p == &self.path) {
+            paths.swap_remove(i);
+        }
+    }
+}
+
+/// Keeps a child's process group registered to be signalled along with us
+/// until dropped, as it no longer gets the terminal's signals itself.
+#[derive(Debug)]
+pub struct GroupGuard {
+    pgid: u32,
+}
+
+impl GroupGuard {
+    pub fn new(pgid: u32) -> Self {
+        CHILD_GROUPS.lock().unwrap().push(pgid);
+        Self { pgid }
+    }
+}
+
+impl Drop for GroupGuard {
+    fn drop(&mut self) {
+        let mut groups = CHILD_GROUPS.lock().unwrap();
+        if let Some(i) = groups.iter().position(|g| *g == self.pgid) {
+            groups.swap_remove(i);
+        }
+    }
+}
+
+/// On SIGINT or SIGTERM, remove the temporary files in progress and exit
+/// with 128 plus the signal number, like a shell reports a killed process.
+///
+/// Must be called before any other thread is started: the signals are
+/// blocked in the calling thread, which every later thread inherits, and
+/// waited for on a thread of their own. Child processes start with a clean
+/// signal mask.
+pub fn install_signal_handler() -> Result<(), anyhow::Error> {
+    let mut set = MaybeUninit::<libc::sigset_t>::uninit();
+    // SAFETY: sigemptyset initializes the set before anything else reads it
+    let set = unsafe {
+        libc::sigemptyset(set.as_mut_ptr());
+        libc::sigaddset(set.as_mut_ptr(), libc::SIGINT);
+        libc::sigaddset(set.as_mut_ptr(), libc::SIGTERM);
+        set.assume_init()
+    };
+    // SAFETY: `set` is a valid signal set and the old mask isn't wanted
+    match unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) } {
+        0 => (),
+        e => return Err(io::Error::from_raw_os_error(e)).context("blocking signals"),
+    }
+
+    thread::Builder::new()
+        .name("signals".to_string())
+        .spawn(move || loop {
+            let mut signo = 0;
+            // SAFETY: `set` outlives the loop and `signo` is a valid out pointer
+            if unsafe { libc::sigwait(&set, &mut signo) } != 0 {
+                continue;
+            }
+            // unlike a signal handler, we may wait for whoever holds the lock
+            let groups = CHILD_GROUPS.lock().unwrap_or_else(|e| e.into_inner());
+            for pgid in groups.iter() {
+                // SAFETY: plain kill(2), a negative pid names the group
+                unsafe { libc::kill(-(*pgid as libc::pid_t), signo) };
+            }
+            let paths = IN_PROGRESS.lock().unwrap_or_else(|e| e.into_inner());
+            for path in paths.iter() {
+                let _ = fs::remove_file(path);
+            }
+            std::process::exit(128 + signo);
+        })
+        .context("starting signal thread")?;
+
+    Ok(())
+}
+
+/// Remove temporary files in `outdir` that are older than `max_age`, left
+/// behind by runs that were killed before they could clean up.
+pub fn remove_stale_tempfiles(c: &Ctx, max_age: Duration) -> Result<(), anyhow::Error> {
+    let outdir = &c.config.outdir;
+    for ent in fs::read_dir(outdir).with_context(|| format!("reading {outdir:?}"))? {
+        let ent = ent?;
+        let is_temp = ent
+            .file_name()
+            .to_str()
+            .map(|n| n.starts_with(TEMP_PREFIX))
+            .unwrap_or(false);
+        if !is_temp {
+            continue;
+        }
+        let path = ent.path();
+        // symlinks count too, going by the age of the link itself
+        let modified = match fs::symlink_metadata(&path).and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            // removed by its owner since we listed the directory
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("{path:?}")),
+        };
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or_default();
+        if age < max_age {
+            continue;
+        }
+        match fs::remove_file(&path) {
+            Ok(()) => info!(c.log, "removed stale temporary file {path:?}"),
+            Err(e) => warn!(c.log, "unable to remove stale temporary file {path:?}: {e}"),
+        }
+    }
+
+    Ok(())
+}
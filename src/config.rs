@@ -2,17 +2,191 @@ use std::{
     fs::File,
     io::{BufReader, Read},
     path::{Path, PathBuf},
+    time::Duration,
 };
 
-use anyhow::Context;
+use anyhow::{bail, Context};
+use glob::Pattern;
 use serde::Deserialize;
 
-#[derive(Debug, Deserialize)]
+use crate::{
+    hooks::DEFAULT_HOOK_TIMEOUT, lock::DEFAULT_LOCK_TIMEOUT, source::Representation, DEFAULT_PREFIX,
+};
+
+#[derive(Debug, Clone, Deserialize)]
 pub struct Config {
+    /// May be left out when `destination` blocks are configured instead.
+    #[serde(default)]
     pub outdir: PathBuf,
-    pub number_of_backups: usize,
+    #[serde(flatten)]
+    pub settings: Settings,
+    /// How long to wait for another run against `outdir` to finish, e.g.
+    /// "2m".
+    pub lock_timeout: Option<String>,
+    #[serde(default)]
+    pub hooks: Hooks,
+    pub capture: Option<Vec<Representation>>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    /// `[[destination]]` blocks, each snapshot is committed to all of them.
+    #[serde(default)]
+    pub destination: Vec<Destination>,
+}
+
+/// How backups are named, compressed and retained, at the top level and
+/// for each destination.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct Settings {
     pub prefix: Option<String>,
     pub compression_level: Option<i32>,
+    /// Number of most recent backups to keep, `number_of_backups` in older
+    /// config files.
+    #[serde(alias = "number_of_backups")]
+    pub keep_last: Option<usize>,
+    pub keep_hourly: Option<usize>,
+    pub keep_daily: Option<usize>,
+    pub keep_weekly: Option<usize>,
+    pub keep_monthly: Option<usize>,
+    pub keep_yearly: Option<usize>,
+    /// Remove backups older than this, e.g. "180d".
+    pub max_age: Option<String>,
+    /// Remove the oldest backups once together they take up more than this,
+    /// e.g. "512M".
+    pub max_total_size: Option<String>,
+}
+
+impl Settings {
+    /// The file name prefix of backups.
+    pub fn prefix(&self) -> &str {
+        self.prefix.as_deref().unwrap_or(DEFAULT_PREFIX)
+    }
+
+    /// These settings, with the ones left out taken from `fallback`.
+    pub fn or(&self, fallback: &Settings) -> Settings {
+        // no `..` here, so a new setting can't be forgotten
+        Settings {
+            prefix: self.prefix.clone().or_else(|| fallback.prefix.clone()),
+            compression_level: self.compression_level.or(fallback.compression_level),
+            keep_last: self.keep_last.or(fallback.keep_last),
+            keep_hourly: self.keep_hourly.or(fallback.keep_hourly),
+            keep_daily: self.keep_daily.or(fallback.keep_daily),
+            keep_weekly: self.keep_weekly.or(fallback.keep_weekly),
+            keep_monthly: self.keep_monthly.or(fallback.keep_monthly),
+            keep_yearly: self.keep_yearly.or(fallback.keep_yearly),
+            max_age: self.max_age.clone().or_else(|| fallback.max_age.clone()),
+            max_total_size: self
+                .max_total_size
+                .clone()
+                .or_else(|| fallback.max_total_size.clone()),
+        }
+    }
+
+    pub fn max_age(&self) -> Result<Option<Duration>, anyhow::Error> {
+        self.max_age
+            .as_deref()
+            .map(|age| {
+                humantime::parse_duration(age).with_context(|| format!("invalid max_age {age:?}"))
+            })
+            .transpose()
+    }
+
+    /// `max_total_size` in bytes.
+    pub fn max_total_size(&self) -> Result<Option<u64>, anyhow::Error> {
+        self.max_total_size
+            .as_deref()
+            .map(|size| {
+                parse_size(size).with_context(|| format!("invalid max_total_size {size:?}"))
+            })
+            .transpose()
+    }
+}
+
+/// An outdir with its own naming, compression and retention. Settings left
+/// out fall back to the top level ones.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Destination {
+    /// Selects the destination on the command line, defaults to `outdir`.
+    pub name: Option<String>,
+    pub outdir: PathBuf,
+    #[serde(flatten)]
+    pub settings: Settings,
+}
+
+impl Destination {
+    pub fn name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => self.outdir.display().to_string(),
+        }
+    }
+}
+
+/// Shell commands run at points of a backup, see [`crate::hooks`].
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Hooks {
+    pub pre_backup: Option<String>,
+    pub post_commit: Option<String>,
+    pub on_unchanged: Option<String>,
+    pub on_error: Option<String>,
+    pub post_prune: Option<String>,
+    /// How long a hook may run before it is killed, e.g. "5m".
+    pub timeout: Option<String>,
+}
+
+impl Hooks {
+    pub fn timeout(&self) -> Result<Duration, anyhow::Error> {
+        match self.timeout.as_deref() {
+            Some(timeout) => humantime::parse_duration(timeout)
+                .with_context(|| format!("invalid hooks.timeout {timeout:?}")),
+            None => Ok(DEFAULT_HOOK_TIMEOUT),
+        }
+    }
+}
+
+/// Compiled `include`/`exclude` zone name globs.
+#[derive(Debug)]
+pub struct ZoneFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+}
+
+impl ZoneFilter {
+    /// Returns why `zone` should be skipped, or `None` if it should be kept.
+    ///
+    /// An empty include list keeps every zone, exclusions are applied after.
+    pub fn rejects(&self, zone: &str) -> Option<String> {
+        if !self.include.is_empty() && !self.include.iter().any(|p| p.matches(zone)) {
+            return Some("not matched by any include pattern".to_string());
+        }
+
+        self.exclude
+            .iter()
+            .find(|p| p.matches(zone))
+            .map(|p| format!("matched exclude pattern {:?}", p.as_str()))
+    }
+}
+
+/// Parse a byte count with an optional binary `K`, `M`, `G` or `T` suffix,
+/// as zfs quotas are written.
+fn parse_size(size: &str) -> Result<u64, anyhow::Error> {
+    let size = size.trim();
+    let digits = size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(digits);
+    let shift = match unit.trim_start().to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" | "KIB" => 10,
+        "M" | "MB" | "MIB" => 20,
+        "G" | "GB" | "GIB" => 30,
+        "T" | "TB" | "TIB" => 40,
+        _ => bail!("unknown size unit {unit:?}"),
+    };
+    let number: u64 = number.parse().context("expected a whole number")?;
+
+    number.checked_mul(1 << shift).context("size is too large")
 }
 
 impl Config {
@@ -28,4 +202,75 @@ impl Config {
 
         Ok(config)
     }
+
+    /// The name and effective config of every destination, in the order
+    /// they are configured. Without `[[destination]]` blocks this is the top
+    /// level config alone.
+    pub fn destinations(&self) -> Result<Vec<(String, Config)>, anyhow::Error> {
+        if self.destination.is_empty() {
+            if self.outdir.as_os_str().is_empty() {
+                bail!("either outdir or a [[destination]] must be configured");
+            }
+            return Ok(vec![(self.outdir.display().to_string(), self.clone())]);
+        }
+        if !self.outdir.as_os_str().is_empty() {
+            bail!("outdir can't be combined with [[destination]], move it into a block");
+        }
+
+        let mut destinations: Vec<(String, Config)> = Vec::new();
+        for d in &self.destination {
+            let name = d.name();
+            if destinations.iter().any(|(n, _)| n == &name) {
+                bail!("destination {name:?} is configured more than once");
+            }
+            let config = Config {
+                outdir: d.outdir.clone(),
+                settings: d.settings.or(&self.settings),
+                destination: Vec::new(),
+                ..self.clone()
+            };
+            let prefix = config.settings.prefix();
+            let shared = destinations.iter().find(|(_, other)| {
+                other.outdir == config.outdir && other.settings.prefix() == prefix
+            });
+            if let Some((other, _)) = shared {
+                // they would share _latest and prune each other's backups
+                bail!(
+                    "destinations {other:?} and {name:?} both write {prefix:?} backups to {:?}",
+                    config.outdir
+                );
+            }
+            destinations.push((name, config));
+        }
+
+        Ok(destinations)
+    }
+
+    /// The zone config representations to store in each archive.
+    pub fn capture(&self) -> &[Representation] {
+        self.capture.as_deref().unwrap_or(&Representation::ALL)
+    }
+
+    pub fn lock_timeout(&self) -> Result<Duration, anyhow::Error> {
+        match self.lock_timeout.as_deref() {
+            Some(timeout) => humantime::parse_duration(timeout)
+                .with_context(|| format!("invalid lock_timeout {timeout:?}")),
+            None => Ok(DEFAULT_LOCK_TIMEOUT),
+        }
+    }
+
+    pub fn zone_filter(&self) -> Result<ZoneFilter, anyhow::Error> {
+        let compile = |patterns: &Option<Vec<String>>, kind: &str| {
+            patterns
+                .iter()
+                .flatten()
+                .map(|p| Pattern::new(p).with_context(|| format!("invalid {kind} pattern {p:?}")))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(ZoneFilter {
+            include: compile(&self.include, "include")?,
+            exclude: compile(&self.exclude, "exclude")?,
+        })
+    }
 }
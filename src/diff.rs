@@ -0,0 +1,190 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+    path::Path,
+};
+
+use anyhow::Context;
+use serde::Serialize;
+use similar::TextDiff;
+
+use crate::{
+    archive,
+    semantic::{diff_info, Change},
+    source::Representation,
+};
+
+/// Zone config members of a snapshot, grouped by zone.
+type Zones = BTreeMap<String, BTreeMap<String, Vec<u8>>>;
+
+fn read_zones(path: &Path) -> Result<Zones, anyhow::Error> {
+    let mut zones = Zones::new();
+    for (name, data) in archive::read_members(path)? {
+        if let Some((zone, _)) = Representation::from_member_name(&name) {
+            zones
+                .entry(zone.to_string())
+                .or_default()
+                .insert(name, data);
+        }
+    }
+
+    Ok(zones)
+}
+
+fn display_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Property and resource level changes between two snapshots, see
+/// [`semantic_diff`].
+#[derive(Debug, Serialize)]
+pub struct SemanticDiff {
+    /// File names of the compared snapshots.
+    pub old: String,
+    pub new: String,
+    /// Changes of each zone found in both snapshots, `None` when it changed
+    /// but its `zonecfg info` was not captured. Zones whose resources were
+    /// only reordered are left out.
+    pub zones: BTreeMap<String, Option<Vec<Change>>>,
+    pub only_in_old: Vec<String>,
+    pub only_in_new: Vec<String>,
+}
+
+impl SemanticDiff {
+    pub fn is_empty(&self) -> bool {
+        self.zones.is_empty() && self.only_in_old.is_empty() && self.only_in_new.is_empty()
+    }
+
+    /// Write one `zone: change` line per change, followed by the zones only
+    /// found in one of the snapshots.
+    pub fn write<W: Write>(&self, mut out: W) -> Result<(), anyhow::Error> {
+        for (zone, changes) in &self.zones {
+            match changes {
+                Some(changes) => {
+                    for change in changes {
+                        writeln!(out, "{zone}: {change}")?;
+                    }
+                }
+                None => writeln!(out, "{zone}: changed, but zonecfg info was not captured")?,
+            }
+        }
+        for (zones, name) in [
+            (&self.only_in_old, &self.old),
+            (&self.only_in_new, &self.new),
+        ] {
+            for zone in zones {
+                writeln!(out, "Only in {name}: {zone}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Compare the `zonecfg info` members of the zones in the `old` and `new`
+/// snapshots.
+pub fn semantic_diff(old: &Path, new: &Path) -> Result<SemanticDiff, anyhow::Error> {
+    let old_zones = read_zones(old)?;
+    let new_zones = read_zones(new)?;
+
+    let mut zones = BTreeMap::new();
+    for (zone, new_members) in &new_zones {
+        let old_members = match old_zones.get(zone) {
+            Some(members) if members != new_members => members,
+            _ => continue,
+        };
+        let member = Representation::Info.member_name(zone);
+        let changes = match (old_members.get(&member), new_members.get(&member)) {
+            (Some(before), Some(after)) => {
+                let changes = diff_info(before, after).with_context(|| member.clone())?;
+                if changes.is_empty() {
+                    continue;
+                }
+                Some(changes)
+            }
+            _ => None,
+        };
+        zones.insert(zone.clone(), changes);
+    }
+    let only_in = |zones: &Zones, others: &Zones| {
+        zones
+            .keys()
+            .filter(|z| !others.contains_key(*z))
+            .cloned()
+            .collect()
+    };
+
+    Ok(SemanticDiff {
+        old: display_name(old),
+        new: display_name(new),
+        zones,
+        only_in_old: only_in(&old_zones, &new_zones),
+        only_in_new: only_in(&new_zones, &old_zones),
+    })
+}
+
+/// Write a unified diff of every zone member that differs between the `old`
+/// and `new` snapshots to `out`, followed by the zones only found in one of
+/// them. With `semantic` set, property and resource level changes from the
+/// `zonecfg info` members are written instead of a unified diff.
+///
+/// Returns whether any differences were found.
+pub fn diff_snapshots<W: Write>(
+    old: &Path,
+    new: &Path,
+    semantic: bool,
+    mut out: W,
+) -> Result<bool, anyhow::Error> {
+    if semantic {
+        let diff = semantic_diff(old, new)?;
+        diff.write(out)?;
+        return Ok(!diff.is_empty());
+    }
+
+    let (old_name, new_name) = (display_name(old), display_name(new));
+    let old_zones = read_zones(old)?;
+    let new_zones = read_zones(new)?;
+    let mut changed = false;
+
+    for (zone, new_members) in &new_zones {
+        let old_members = match old_zones.get(zone) {
+            Some(members) => members,
+            None => continue,
+        };
+        let names: BTreeSet<&String> = old_members.keys().chain(new_members.keys()).collect();
+        for name in names {
+            let before = old_members.get(name).map(Vec::as_slice).unwrap_or_default();
+            let after = new_members.get(name).map(Vec::as_slice).unwrap_or_default();
+            if before == after {
+                continue;
+            }
+            changed = true;
+            let (before, after) = (
+                String::from_utf8_lossy(before),
+                String::from_utf8_lossy(after),
+            );
+            let diff = TextDiff::from_lines(before.as_ref(), after.as_ref());
+            write!(
+                out,
+                "{}",
+                diff.unified_diff()
+                    .header(&format!("{old_name}:{name}"), &format!("{new_name}:{name}"))
+            )?;
+        }
+    }
+
+    for (zones, others, name) in [
+        (&old_zones, &new_zones, &old_name),
+        (&new_zones, &old_zones, &new_name),
+    ] {
+        for zone in zones.keys().filter(|z| !others.contains_key(*z)) {
+            changed = true;
+            writeln!(out, "Only in {name}: {zone}")?;
+        }
+    }
+
+    Ok(changed)
+}
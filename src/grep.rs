@@ -0,0 +1,94 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use regex::Regex;
+use serde::Serialize;
+use slog::warn;
+
+use crate::{archive, find_latest_snapshot, find_snapshots, source::Representation, Ctx};
+
+/// A line of a zone member matching the searched pattern.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Match {
+    pub archive: PathBuf,
+    pub zone: String,
+    pub member: String,
+    /// 1-based line number within the member.
+    pub line_number: usize,
+    pub line: String,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct GrepOptions {
+    /// Only search the backup `_latest` points at.
+    pub latest: bool,
+    /// Only search these zones, all of them when empty.
+    pub zones: Vec<String>,
+}
+
+/// Search every zone member of every backup in `outdir`, newest first, for
+/// lines matching `pattern`. Archives that cannot be read are skipped with a
+/// warning.
+pub fn grep_snapshots(
+    c: &Ctx,
+    pattern: &Regex,
+    opts: &GrepOptions,
+) -> Result<Vec<Match>, anyhow::Error> {
+    let snapshots = match opts.latest {
+        true => find_latest_snapshot(c)?.into_iter().collect(),
+        false => find_snapshots(c)?,
+    };
+
+    let mut matches = Vec::new();
+    for path in snapshots {
+        if let Err(e) = grep_archive(&path, pattern, opts, &mut matches) {
+            warn!(c.log, "skipping unreadable {path:?}: {e:#}");
+        }
+    }
+
+    Ok(matches)
+}
+
+fn grep_archive(
+    path: &Path,
+    pattern: &Regex,
+    opts: &GrepOptions,
+    matches: &mut Vec<Match>,
+) -> Result<(), anyhow::Error> {
+    let mut archive = archive::open(path)?;
+    for entry in archive
+        .entries()
+        .with_context(|| format!("reading {path:?}"))?
+    {
+        let mut entry = entry.with_context(|| format!("reading {path:?}"))?;
+        let member = entry.path()?.to_string_lossy().into_owned();
+        let zone = match Representation::from_member_name(&member) {
+            Some((zone, _)) => zone.to_string(),
+            None => continue,
+        };
+        if !opts.zones.is_empty() && !opts.zones.contains(&zone) {
+            continue;
+        }
+
+        let mut buf = Vec::with_capacity(entry.size() as usize);
+        entry
+            .read_to_end(&mut buf)
+            .with_context(|| format!("reading {member} from {path:?}"))?;
+        for (i, line) in String::from_utf8_lossy(&buf).lines().enumerate() {
+            if pattern.is_match(line) {
+                matches.push(Match {
+                    archive: path.to_path_buf(),
+                    zone: zone.clone(),
+                    member: member.clone(),
+                    line_number: i + 1,
+                    line: line.to_string(),
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
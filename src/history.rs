@@ -0,0 +1,92 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use slog::warn;
+
+use crate::{
+    archive, find_snapshots,
+    semantic::{diff_info, Change},
+    snapshot_time,
+    source::Representation,
+    Ctx,
+};
+
+/// What happened to a zone in a given snapshot.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "event", rename_all = "lowercase")]
+pub enum Event {
+    Appeared,
+    /// The zone's members differ from the previous snapshot it was in.
+    /// `changes` is empty when `zonecfg info` was not captured in both.
+    Changed {
+        changes: Vec<Change>,
+    },
+    Disappeared,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    pub archive: PathBuf,
+    pub timestamp: Option<DateTime<Utc>>,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// The zone as found in the last snapshot it was in.
+struct Seen {
+    hashes: BTreeMap<String, String>,
+    info: Option<Vec<u8>>,
+}
+
+/// Walk every backup in `outdir` from oldest to newest and record each
+/// snapshot in which `zone` appeared, changed or disappeared. Archives that
+/// cannot be read are skipped with a warning.
+pub fn zone_history(c: &Ctx, zone: &str) -> Result<Vec<HistoryEntry>, anyhow::Error> {
+    let info_member = Representation::Info.member_name(zone);
+    let mut history = Vec::new();
+    let mut previous: Option<Seen> = None;
+
+    for path in find_snapshots(c)?.into_iter().rev() {
+        let members = match archive::zone_hashes(&path) {
+            Ok(mut hashes) => hashes.remove(zone),
+            Err(e) => {
+                warn!(c.log, "skipping unreadable {path:?}: {e:#}");
+                continue;
+            }
+        };
+
+        let event = match (previous.take(), members) {
+            (None, None) => continue,
+            (Some(_), None) => Event::Disappeared,
+            (None, Some(hashes)) => {
+                let info = archive::read_member(&path, &info_member)?;
+                previous = Some(Seen { hashes, info });
+                Event::Appeared
+            }
+            (Some(seen), Some(hashes)) if seen.hashes == hashes => {
+                previous = Some(seen);
+                continue;
+            }
+            (Some(seen), Some(hashes)) => {
+                let info = archive::read_member(&path, &info_member)?;
+                let changes = match (&seen.info, &info) {
+                    (Some(before), Some(after)) => diff_info(before, after)
+                        .with_context(|| format!("{info_member} in {path:?}"))?,
+                    _ => Vec::new(),
+                };
+                previous = Some(Seen { hashes, info });
+                Event::Changed { changes }
+            }
+        };
+
+        history.push(HistoryEntry {
+            timestamp: snapshot_time(c, &path),
+            archive: path,
+            event,
+        });
+    }
+
+    Ok(history)
+}
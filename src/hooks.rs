@@ -0,0 +1,127 @@
+use std::{
+    io,
+    os::unix::process::CommandExt,
+    process::{Child, Command, Stdio},
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use slog::{debug, info};
+use wait_timeout::ChildExt;
+
+use crate::{cleanup::GroupGuard, file_prefix, find_latest_snapshot, report::ChangeReport, Ctx};
+
+/// How long a hook may run when `hooks.timeout` isn't configured.
+pub const DEFAULT_HOOK_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    PreBackup,
+    PostCommit,
+    OnUnchanged,
+    OnError,
+    PostPrune,
+}
+
+impl Hook {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::PreBackup => "pre_backup",
+            Hook::PostCommit => "post_commit",
+            Hook::OnUnchanged => "on_unchanged",
+            Hook::OnError => "on_error",
+            Hook::PostPrune => "post_prune",
+        }
+    }
+
+    fn command<'a>(&self, c: &'a Ctx) -> Option<&'a str> {
+        let hooks = &c.config.hooks;
+        match self {
+            Hook::PreBackup => hooks.pre_backup.as_deref(),
+            Hook::PostCommit => hooks.post_commit.as_deref(),
+            Hook::OnUnchanged => hooks.on_unchanged.as_deref(),
+            Hook::OnError => hooks.on_error.as_deref(),
+            Hook::PostPrune => hooks.post_prune.as_deref(),
+        }
+    }
+}
+
+/// Environment variables describing a commit's zone changes.
+pub fn change_vars(changes: &ChangeReport) -> Vec<(&'static str, String)> {
+    let mut changed: Vec<&String> = changes
+        .added
+        .iter()
+        .chain(&changes.removed)
+        .chain(&changes.modified)
+        .collect();
+    changed.sort();
+
+    vec![
+        ("ZCB_CHANGED_ZONES", join(changed)),
+        ("ZCB_ADDED_ZONES", join(&changes.added)),
+        ("ZCB_REMOVED_ZONES", join(&changes.removed)),
+        ("ZCB_MODIFIED_ZONES", join(&changes.modified)),
+    ]
+}
+
+fn join<I: IntoIterator<Item = S>, S: ToString>(items: I) -> String {
+    items
+        .into_iter()
+        .map(|i| i.to_string())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// SIGKILL every process in the group `child` leads.
+fn kill_group(child: &Child) {
+    // SAFETY: plain kill(2), a negative pid names the group
+    unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+}
+
+/// Run `hook` with `/bin/sh -c` if it is configured, with `vars` added to
+/// the `ZCB_*` variables every hook gets. Exiting non-zero or running past
+/// `hooks.timeout` is an error. The hook's output goes to stderr so it can't
+/// mix with JSON results.
+pub fn run_hook(c: &Ctx, hook: Hook, vars: &[(&str, String)]) -> Result<(), anyhow::Error> {
+    let command = match hook.command(c) {
+        Some(command) => command,
+        None => return Ok(()),
+    };
+    let name = hook.name();
+    let timeout = c.config.hooks.timeout()?;
+    let latest = find_latest_snapshot(c)?
+        .map(|l| l.display().to_string())
+        .unwrap_or_default();
+
+    info!(c.log, "running {name} hook");
+    debug!(c.log, "{name}: {command}");
+    let mut child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .env("ZCB_HOOK", name)
+        .env("ZCB_OUTDIR", &c.config.outdir)
+        .env("ZCB_PREFIX", file_prefix(c))
+        .env("ZCB_LATEST", latest)
+        .envs(vars.iter().map(|(k, v)| (k, v)))
+        .stdin(Stdio::null())
+        .stdout(io::stderr())
+        // its own group, so that a timeout kills whatever it started too
+        .process_group(0)
+        .spawn()
+        .with_context(|| format!("failed to run {name} hook"))?;
+    let _group = GroupGuard::new(child.id());
+
+    let status = match child.wait_timeout(timeout)? {
+        Some(status) => status,
+        None => {
+            kill_group(&child);
+            child.wait()?;
+            bail!("{name} hook timed out after {timeout:?}");
+        }
+    };
+    if !status.success() {
+        bail!("{name} hook failed: {status}");
+    }
+
+    Ok(())
+}
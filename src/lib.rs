@@ -0,0 +1,593 @@
+use anyhow::{bail, Context};
+use chrono::{DateTime, TimeZone, Utc};
+use cleanup::{TempGuard, TEMP_PREFIX};
+use config::Config;
+use manifest::{FailedZone, Manifest, ZoneEntry, MANIFEST_NAME};
+use report::ChangeReport;
+use retention::{Limits, Retention};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use slog::{debug, info, o, warn, Drain, Level, LevelFilter, Logger};
+use source::ZoneSource;
+use std::{
+    cmp::Reverse,
+    collections::BTreeMap,
+    fmt,
+    fs::{self, read_dir},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+use tar::Header;
+
+pub mod archive;
+pub mod cleanup;
+pub mod config;
+pub mod diff;
+pub mod grep;
+pub mod history;
+pub mod hooks;
+pub mod lock;
+pub mod manifest;
+pub mod report;
+pub mod restore;
+pub mod retention;
+pub mod run;
+pub mod semantic;
+pub mod source;
+pub mod verify;
+pub mod zonecfg;
+
+pub const DEFAULT_PREFIX: &str = "zonecfg-backup";
+pub const DEFAULT_COMPRESSION_LEVEL: i32 = 10;
+pub const SIDECAR_SUFFIX: &str = ".sha256";
+
+pub struct Ctx {
+    pub config: Config,
+    pub log: Logger,
+}
+
+pub fn file_prefix(c: &Ctx) -> &str {
+    c.config.settings.prefix()
+}
+
+pub fn file_latest(c: &Ctx) -> PathBuf {
+    c.config.outdir.join(format!("{}_latest", file_prefix(c)))
+}
+
+/// Log at `level` and above, to stderr when stdout is reserved for
+/// machine readable output.
+pub fn create_logger(level: Level, stderr: bool) -> Logger {
+    let out: Box<dyn io::Write + Send> = match stderr {
+        true => Box::new(io::stderr()),
+        false => Box::new(io::stdout()),
+    };
+    let plain = slog_term::PlainSyncDecorator::new(out);
+    let drain = slog_term::FullFormat::new(plain).build().fuse();
+    Logger::root(LevelFilter::new(drain, level).fuse(), o!())
+}
+
+/// The file name of a backup, `{prefix}_{timestamp}.zones.tar.zst`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupName {
+    pub prefix: String,
+    /// Unix timestamp the backup was committed at.
+    pub timestamp: i64,
+}
+
+impl BackupName {
+    pub const SUFFIX: &'static str = ".zones.tar.zst";
+
+    /// Parse a backup file name written with `prefix`. Anything that would not
+    /// be formatted back to exactly the same name, such as a timestamp with a
+    /// sign or leading zeros, is rejected.
+    pub fn parse(prefix: &str, file_name: &str) -> Option<Self> {
+        let timestamp = file_name
+            .strip_prefix(prefix)?
+            .strip_prefix('_')?
+            .strip_suffix(Self::SUFFIX)?;
+        if timestamp.is_empty() || !timestamp.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let name = Self {
+            prefix: prefix.to_string(),
+            timestamp: timestamp.parse().ok()?,
+        };
+
+        (name.to_string() == file_name).then_some(name)
+    }
+
+    pub fn time(&self) -> Option<DateTime<Utc>> {
+        Utc.timestamp_opt(self.timestamp, 0).single()
+    }
+}
+
+impl fmt::Display for BackupName {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}_{}{}", self.prefix, self.timestamp, Self::SUFFIX)
+    }
+}
+
+pub fn file_snapshot(c: &Ctx, timestamp: i64) -> PathBuf {
+    let name = BackupName {
+        prefix: file_prefix(c).to_string(),
+        timestamp,
+    };
+    c.config.outdir.join(name.to_string())
+}
+
+/// The `sha256sum` compatible checksum file kept next to a backup.
+pub fn file_sidecar(archive: &Path) -> PathBuf {
+    let mut name = archive.as_os_str().to_owned();
+    name.push(SIDECAR_SUFFIX);
+    PathBuf::from(name)
+}
+
+/// Write the SHA-256 of `archive` to its sidecar file, in the format
+/// `sha256sum -c` expects.
+pub fn write_sidecar(archive: &Path) -> Result<(), anyhow::Error> {
+    let f = fs::File::open(archive).with_context(|| format!("{archive:?}"))?;
+    let hash = generate_hash(f).with_context(|| format!("hashing {archive:?}"))?;
+    let name = archive.file_name().unwrap_or_default().to_string_lossy();
+    let sidecar = file_sidecar(archive);
+    let mut f = fs::File::create(&sidecar).with_context(|| format!("{sidecar:?}"))?;
+    writeln!(f, "{hash}  {name}")
+        .and_then(|_| f.sync_all())
+        .with_context(|| format!("{sidecar:?}"))
+}
+
+/// Flush a directory's entries to disk, so that files created or renamed in
+/// it survive a crash.
+pub fn sync_dir(dir: &Path) -> Result<(), anyhow::Error> {
+    fs::File::open(dir)
+        .and_then(|d| d.sync_all())
+        .with_context(|| format!("syncing {dir:?}"))
+}
+
+/// Point the symlink `link` at `target` without a moment where `link` doesn't
+/// exist, by creating it under a temporary name and renaming it into place.
+pub fn replace_symlink(target: &Path, link: &Path) -> Result<(), anyhow::Error> {
+    let dir = link.parent().unwrap_or(Path::new("."));
+    let tmp = dir.join(format!("{TEMP_PREFIX}.latest.{}", std::process::id()));
+    let _guard = TempGuard::new(&tmp);
+
+    let _ = fs::remove_file(&tmp);
+    std::os::unix::fs::symlink(target, &tmp)
+        .with_context(|| format!("symlink {target:?} -> {tmp:?}"))?;
+    if let Err(e) = fs::rename(&tmp, link) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e).with_context(|| format!("renaming {tmp:?} to {link:?}"));
+    }
+
+    Ok(())
+}
+
+/// The time encoded in a backup's file name, if it is one of ours.
+pub fn snapshot_time(c: &Ctx, path: &Path) -> Option<DateTime<Utc>> {
+    let name = path.file_name()?.to_str()?;
+    BackupName::parse(file_prefix(c), name)?.time()
+}
+
+/// The backup `_latest` points at, `None` if the link is missing or dangling.
+/// A relative link is resolved against `outdir`, where the link lives.
+pub fn find_latest_snapshot(c: &Ctx) -> Result<Option<PathBuf>, anyhow::Error> {
+    let latest = file_latest(c);
+    if Path::exists(&latest) {
+        let target = fs::read_link(&latest).with_context(|| format!("reading link {latest:?}"))?;
+        return Ok(Some(c.config.outdir.join(target)));
+    }
+
+    Ok(None)
+}
+
+/// Resolve a user supplied snapshot: `None` or "latest" for the current
+/// `_latest` target, a bare unix timestamp for that backup in `outdir`, or
+/// otherwise a path to an archive.
+pub fn resolve_snapshot(c: &Ctx, at: Option<&str>) -> Result<PathBuf, anyhow::Error> {
+    let path = match at {
+        None | Some("latest") => match find_latest_snapshot(c)? {
+            Some(latest) => latest,
+            None => bail!("no {:?} snapshot found", file_latest(c)),
+        },
+        Some(at) => match at.parse::<i64>() {
+            Ok(timestamp) => file_snapshot(c, timestamp),
+            Err(_) => PathBuf::from(at),
+        },
+    };
+
+    if !path.exists() {
+        bail!("snapshot {path:?} does not exist");
+    }
+
+    Ok(path)
+}
+
+/// A captured snapshot archive that has not been committed to `outdir` yet.
+pub struct Snapshot {
+    pub file: tempfile::NamedTempFile,
+    pub manifest: Manifest,
+    /// Removes `file` should we be interrupted before it's committed.
+    _guard: TempGuard,
+}
+
+/// Zone configs captured from a source, ready to be written to any number of
+/// destinations.
+pub struct Capture {
+    pub manifest: Manifest,
+    /// Archive members in the order they are written.
+    pub members: Vec<(String, Vec<u8>)>,
+}
+
+/// Capture every zone config `c` selects, without writing anything.
+pub fn capture_zone_configs<S: ZoneSource + ?Sized>(
+    c: &Ctx,
+    source: &S,
+) -> Result<Capture, anyhow::Error> {
+    let mut manifest = Manifest::new(file_prefix(c), Utc::now())?;
+    let filter = c.config.zone_filter()?;
+    let mut zones = source.find_zones()?;
+    zones.retain(|zone| match filter.rejects(&zone.name) {
+        Some(reason) => {
+            debug!(&c.log, "skipping zone {}: {reason}", zone.name);
+            false
+        }
+        None => true,
+    });
+
+    // capture everything up front so the manifest can lead the archive
+    let mut members = Vec::new();
+    for zone in zones {
+        let name = &zone.name;
+        let captured = c
+            .config
+            .capture()
+            .iter()
+            .map(|&repr| source.get_zonecfg(name, repr).map(|data| (repr, data)))
+            .collect::<Result<Vec<_>, _>>();
+        match captured {
+            Ok(captured) => {
+                let mut hashes = BTreeMap::new();
+                for (repr, data) in captured {
+                    let member = repr.member_name(name);
+                    hashes.insert(member.clone(), generate_hash(data.as_slice())?);
+                    members.push((member, data));
+                }
+                info!(&c.log, "appending zone {name}");
+                manifest.zones.push(ZoneEntry {
+                    info: zone,
+                    members: hashes,
+                });
+            }
+            // perhaps the zone no longer exists, let's log an error and move on
+            Err(e) => {
+                warn!(c.log, "no info for {name}: {e:?}");
+                manifest.failed.push(FailedZone {
+                    name: zone.name,
+                    error: format!("{e:#}"),
+                });
+            }
+        }
+    }
+
+    Ok(Capture { manifest, members })
+}
+
+impl Capture {
+    /// Write the captured zone configs to a temporary archive in `outdir`,
+    /// named and compressed as configured in `c`.
+    pub fn write_snapshot(&self, c: &Ctx) -> Result<Snapshot, anyhow::Error> {
+        self.write_snapshot_in(c, &c.config.outdir)
+    }
+
+    /// Like [`Capture::write_snapshot`], but with the temporary archive in
+    /// `dir`. Such a snapshot can be planned but not committed, unless `dir`
+    /// is on the same filesystem as `outdir`.
+    pub fn write_snapshot_in(&self, c: &Ctx, dir: &Path) -> Result<Snapshot, anyhow::Error> {
+        let mut manifest = self.manifest.clone();
+        manifest.prefix = file_prefix(c).to_string();
+
+        let tempfile = tempfile::Builder::new()
+            .prefix(TEMP_PREFIX)
+            .tempfile_in(dir)
+            .with_context(|| format!("creating temporary file in {dir:?}"))?;
+        let guard = TempGuard::new(tempfile.path());
+        let level = c
+            .config
+            .settings
+            .compression_level
+            .unwrap_or(DEFAULT_COMPRESSION_LEVEL);
+        let mut encoder = zstd::Encoder::new(tempfile, level)?;
+        {
+            let mut a = tar::Builder::new(&mut encoder);
+            let manifest_data = serde_json::to_vec_pretty(&manifest)?;
+            let entries = std::iter::once((MANIFEST_NAME, &manifest_data)).chain(
+                self.members
+                    .iter()
+                    .map(|(name, data)| (name.as_str(), data)),
+            );
+            for (member, data) in entries {
+                let mut header = Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_cksum();
+                a.append_data(&mut header, member, data.as_slice())?;
+            }
+            a.finish()?;
+        }
+
+        Ok(Snapshot {
+            file: encoder.finish()?,
+            manifest,
+            _guard: guard,
+        })
+    }
+}
+
+pub fn snapshot_zone_configs<S: ZoneSource + ?Sized>(
+    c: &Ctx,
+    source: &S,
+) -> Result<Snapshot, anyhow::Error> {
+    capture_zone_configs(c, source)?.write_snapshot(c)
+}
+
+pub fn generate_hash<R: Read>(mut input: R) -> Result<String, anyhow::Error> {
+    use std::fmt::Write;
+
+    let mut hasher = Sha256::new();
+    let mut out = String::new();
+
+    io::copy(&mut input, &mut hasher)?;
+    for byte in hasher.finalize().iter() {
+        let _ = write!(out, "{byte:02x}");
+    }
+
+    Ok(out)
+}
+
+/// The outcome of committing a snapshot.
+#[derive(Debug, Serialize)]
+pub struct Commit {
+    /// Path of the newly written archive, `None` if nothing changed.
+    pub archive: Option<PathBuf>,
+    /// Zones that differ from the previous `_latest` snapshot.
+    pub changes: ChangeReport,
+}
+
+/// Work out what committing `snapshot` would do without touching `outdir`.
+/// The returned `archive` is where it would be written.
+pub fn plan_commit(c: &Ctx, snapshot: &Snapshot) -> Result<Commit, anyhow::Error> {
+    // Compare the uncompressed zone contents rather than the archive bytes, so
+    // that zstd upgrades or a new compression_level don't look like changes.
+    let latest = find_latest_snapshot(c)?;
+    let previous = match &latest {
+        Some(latest) => Some(archive::zone_hashes(latest).with_context(|| format!("{latest:?}"))?),
+        None => None,
+    };
+    let mut changes = ChangeReport::between(
+        previous.as_ref().unwrap_or(&Default::default()),
+        &snapshot.manifest.zone_hashes(),
+    );
+
+    if previous.is_some() && changes.is_empty() {
+        return Ok(Commit {
+            archive: None,
+            changes,
+        });
+    }
+
+    if let Some(latest) = &latest {
+        // details are a nicety, an unparsable config shouldn't stop the backup
+        if let Err(e) = changes.describe(latest, snapshot.file.path()) {
+            warn!(c.log, "unable to describe zone changes: {e:#}");
+        }
+    }
+
+    Ok(Commit {
+        archive: Some(file_snapshot(c, Utc::now().timestamp())),
+        changes,
+    })
+}
+
+pub fn try_commit_zone_snapshot(c: &Ctx, snapshot: Snapshot) -> Result<Commit, anyhow::Error> {
+    let commit = plan_commit(c, &snapshot)?;
+    let path = match &commit.archive {
+        Some(path) => path,
+        None => {
+            info!(
+                &c.log,
+                "No changes in zone configs detected, skipping write."
+            );
+            return Ok(commit);
+        }
+    };
+    let latest_path = file_latest(c);
+
+    commit.changes.log(&c.log);
+    snapshot
+        .file
+        .as_file()
+        .sync_all()
+        .with_context(|| format!("syncing {:?}", snapshot.file.path()))?;
+    snapshot
+        .file
+        .persist(path)
+        .with_context(|| format!("{path:?}"))?;
+    write_sidecar(path)?;
+    // make sure the archive's name is on disk before _latest points at it
+    sync_dir(&c.config.outdir)?;
+    info!(&c.log, "zone backup file written to {path:?}");
+    replace_symlink(path, &latest_path)?;
+    sync_dir(&c.config.outdir)?;
+    info!(&c.log, "symlinked {path:?} to {latest_path:?}");
+
+    Ok(commit)
+}
+
+/// A backup found in `outdir`.
+#[derive(Debug, Clone)]
+pub struct Backup {
+    pub name: BackupName,
+    pub path: PathBuf,
+}
+
+/// All backups in `outdir`, newest first. Only regular files whose name
+/// parses as a [`BackupName`] with our prefix are considered.
+pub fn find_backups(c: &Ctx) -> Result<Vec<Backup>, anyhow::Error> {
+    let prefix = file_prefix(c);
+    let mut backups = Vec::new();
+    for ent in
+        read_dir(&c.config.outdir).with_context(|| format!("reading {:?}", c.config.outdir))?
+    {
+        let ent = ent?;
+        let name = match ent
+            .file_name()
+            .to_str()
+            .and_then(|n| BackupName::parse(prefix, n))
+        {
+            Some(name) => name,
+            None => continue,
+        };
+        if !ent.file_type()?.is_file() {
+            continue;
+        }
+        backups.push(Backup {
+            name,
+            path: ent.path(),
+        });
+    }
+    // sort them so that the oldest backups come last
+    backups.sort_by_key(|b| Reverse(b.name.timestamp));
+
+    Ok(backups)
+}
+
+/// Paths of all backups in `outdir`, newest first.
+pub fn find_snapshots(c: &Ctx) -> Result<Vec<PathBuf>, anyhow::Error> {
+    Ok(find_backups(c)?.into_iter().map(|b| b.path).collect())
+}
+
+/// A backup in `outdir` as shown by `list`.
+#[derive(Debug, Serialize)]
+pub struct BackupSummary {
+    pub path: PathBuf,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub size: u64,
+    /// Zones held by the archive, `None` if it could not be read.
+    pub zones: Option<Vec<String>>,
+    /// Whether `_latest` points at this backup.
+    pub latest: bool,
+}
+
+/// Summarize every backup in `outdir`, newest first. Archives that fail to
+/// decode are still listed, without their zones.
+pub fn summarize_snapshots(c: &Ctx) -> Result<Vec<BackupSummary>, anyhow::Error> {
+    let latest = find_latest_snapshot(c)?;
+    let mut summaries = Vec::new();
+    for path in find_snapshots(c)? {
+        let size = fs::metadata(&path)
+            .with_context(|| format!("{path:?}"))?
+            .len();
+        let zones = match archive::zone_hashes(&path) {
+            Ok(hashes) => Some(hashes.into_keys().collect()),
+            Err(e) => {
+                warn!(c.log, "failed to read {path:?}: {e:#}");
+                None
+            }
+        };
+        summaries.push(BackupSummary {
+            timestamp: snapshot_time(c, &path),
+            latest: latest.as_ref().map(|l| l.file_name()) == Some(path.file_name()),
+            path,
+            size,
+            zones,
+        });
+    }
+
+    Ok(summaries)
+}
+
+/// The backup taken before the current `_latest` one, if any.
+pub fn find_previous_snapshot(c: &Ctx) -> Result<Option<PathBuf>, anyhow::Error> {
+    let snapshots = find_snapshots(c)?;
+    let latest = match find_latest_snapshot(c)? {
+        Some(latest) => latest,
+        None => return Ok(None),
+    };
+
+    Ok(snapshots
+        .into_iter()
+        .skip_while(|s| s.file_name() != latest.file_name())
+        .nth(1))
+}
+
+/// The backups pruning would remove, newest first: those the configured
+/// [`Retention`] doesn't keep or that exceed the configured [`Limits`]. The
+/// `_latest` backup is never among them.
+///
+/// `pending` is the path and size of a backup that is about to be committed
+/// and become `_latest`, for planning ahead of the commit.
+pub fn find_prunable(
+    c: &Ctx,
+    pending: Option<(&Path, u64)>,
+) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let mut backups = Vec::new();
+    for backup in find_backups(c)? {
+        let size = fs::metadata(&backup.path)
+            .with_context(|| format!("{:?}", backup.path))?
+            .len();
+        backups.push((backup, size));
+    }
+    let mut latest = find_latest_snapshot(c)?;
+    if let Some((path, size)) = pending {
+        let name = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(|n| BackupName::parse(file_prefix(c), n));
+        let name = match name {
+            Some(name) => name,
+            None => bail!("{path:?} is not a backup file name"),
+        };
+        let path = path.to_path_buf();
+        latest = Some(path.clone());
+        backups.push((Backup { name, path }, size));
+        backups.sort_by_key(|(b, _)| Reverse(b.name.timestamp));
+    }
+
+    let latest = backups
+        .iter()
+        .position(|(b, _)| latest.as_ref().map(|l| l.file_name()) == Some(b.path.file_name()));
+    let sizes: Vec<_> = backups
+        .iter()
+        .map(|(b, size)| (b.name.time(), *size))
+        .collect();
+    let times: Vec<_> = sizes.iter().map(|(time, _)| *time).collect();
+    let mut keep = Retention::from_config(&c.config).apply(&times);
+    Limits::from_config(&c.config)?.apply(Utc::now(), &sizes, latest, &mut keep);
+
+    let mut prunable = Vec::new();
+    for ((Backup { path, .. }, _), keep) in backups.into_iter().zip(keep) {
+        match keep {
+            Some(reason) => debug!(&c.log, "keeping {path:?} ({})", reason.as_str()),
+            None => prunable.push(path),
+        }
+    }
+
+    Ok(prunable)
+}
+
+/// Remove the backups [`find_prunable`] picks, along with their checksum
+/// sidecars, returning the removed backups.
+pub fn prune_zonecfg_backups(c: &Ctx) -> Result<Vec<PathBuf>, anyhow::Error> {
+    let prunable = find_prunable(c, None)?;
+    for path in &prunable {
+        fs::remove_file(path).with_context(|| format!("removing file {path:?}"))?;
+        let sidecar = file_sidecar(path);
+        match fs::remove_file(&sidecar) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                warn!(&c.log, "unable to remove {sidecar:?}: {e}")
+            }
+            _ => (),
+        }
+        info!(&c.log, "pruned {path:?}")
+    }
+
+    Ok(prunable)
+}
@@ -0,0 +1,90 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+
+/// Name of the lock file in `outdir`, shared by every prefix writing there.
+pub const LOCK_NAME: &str = ".zonecfg-backup.lock";
+
+/// How long to wait for the lock when `lock_timeout` isn't configured.
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Take an exclusive `flock(2)` on `file` without blocking. The lock is
+/// released when the file is closed.
+fn try_flock(file: &File) -> io::Result<()> {
+    // SAFETY: flock only operates on the descriptor, which `file` keeps open
+    match unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+/// An exclusive advisory lock on an `outdir`, held until dropped.
+///
+/// The lock file records the PID of the holder so that whoever is kept
+/// waiting can be told who to look for.
+#[derive(Debug)]
+pub struct RunLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl RunLock {
+    /// Lock `dir`, retrying for up to `timeout` while another process holds
+    /// it.
+    pub fn acquire(dir: &Path, timeout: Duration) -> Result<Self, anyhow::Error> {
+        let path = dir.join(LOCK_NAME);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("{path:?}"))?;
+
+        let start = Instant::now();
+        loop {
+            match try_flock(&file) {
+                Ok(()) => break,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    if start.elapsed() >= timeout {
+                        let mut holder = String::new();
+                        let _ = file.read_to_string(&mut holder);
+                        let holder = match holder.trim() {
+                            "" => "another process".to_string(),
+                            pid => format!("pid {pid}"),
+                        };
+                        bail!("{path:?} is locked by {holder}, gave up after {timeout:?}");
+                    }
+                    thread::sleep(Duration::from_millis(100).min(timeout));
+                }
+                Err(e) => return Err(e).with_context(|| format!("locking {path:?}")),
+            }
+        }
+
+        file.set_len(0)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| writeln!(file, "{}", std::process::id()))
+            .with_context(|| format!("{path:?}"))?;
+
+        Ok(Self { file, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // clear our pid while still holding the lock, closing the file
+        // releases it
+        let _ = self.file.set_len(0);
+    }
+}
@@ -1,225 +1,488 @@
-use anyhow::{bail, Context};
-use chrono::Utc;
-use config::Config;
-use sha2::{Digest, Sha256};
-use slog::{info, o, warn, Drain, Logger};
 use std::{
-    cmp::Reverse,
-    fs::{self, read_dir, File},
-    io::{self, BufRead, Read},
+    fs,
+    io::{self, Write},
     path::{Path, PathBuf},
-    process::Command,
 };
-use tar::Header;
 
-mod config;
+use anyhow::{bail, Context};
+use chrono::{DateTime, Utc};
+use clap::{Parser, Subcommand, ValueEnum};
+use regex::Regex;
+use serde::Serialize;
+use slog::Level;
+use zonecfg_backup::{
+    archive,
+    cleanup::install_signal_handler,
+    config::Config,
+    create_logger,
+    diff::{diff_snapshots, semantic_diff},
+    file_latest, find_previous_snapshot, find_snapshots,
+    grep::{grep_snapshots, GrepOptions},
+    history::{zone_history, Event},
+    resolve_snapshot,
+    restore::{restore_zones, RestoreOptions},
+    retention::Limits,
+    run::{self, Commits, Destination},
+    source::{CommandSource, Representation},
+    summarize_snapshots,
+    verify::{verify_archive, verify_latest, Verified},
+    zonecfg::ZoneConfig,
+    Ctx,
+};
 
-const ZONEADM: &str = "/usr/sbin/zoneadm";
-const ZONECFG: &str = "/usr/sbin/zonecfg";
-const DEFAULT_PREFIX: &str = "zonecfg-backup";
-const DEFAULT_COMPRESSION_LEVEL: i32 = 10;
+/// Backup zone configurations for all configured zones.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Path to the config file
+    #[arg(short, long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// Only log messages at or above this level
+    #[arg(long, global = true, value_enum, default_value_t = LogLevel::Info)]
+    log_level: LogLevel,
+
+    /// Output format for command results
+    #[arg(short, long, global = true, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Only use the destination with this name, by default backup, prune and
+    /// verify use all of them and other commands the first
+    #[arg(short, long, global = true, value_name = "NAME")]
+    destination: Option<String>,
+
+    /// Config file for a backup, as accepted before subcommands existed
+    #[arg(hide = true)]
+    legacy_config: Option<PathBuf>,
+
+    /// Defaults to taking a backup
+    #[command(subcommand)]
+    command: Option<Cmd>,
+}
 
-struct Ctx {
-    config: Config,
-    log: Logger,
+#[derive(Clone, Copy, ValueEnum)]
+enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
 }
 
-fn file_prefix(c: &Ctx) -> &str {
-    c.config.prefix.as_deref().unwrap_or(DEFAULT_PREFIX)
+impl From<LogLevel> for Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => Level::Error,
+            LogLevel::Warn => Level::Warning,
+            LogLevel::Info => Level::Info,
+            LogLevel::Debug => Level::Debug,
+            LogLevel::Trace => Level::Trace,
+        }
+    }
 }
 
-fn file_latest(c: &Ctx) -> PathBuf {
-    c.config.outdir.join(format!("{}_latest", file_prefix(c)))
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
 }
 
-fn create_logger() -> Logger {
-    let plain = slog_term::PlainSyncDecorator::new(std::io::stdout());
-    Logger::root(slog_term::FullFormat::new(plain).build().fuse(), o!())
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum ShowFormat {
+    /// `zonecfg info` output as captured
+    Info,
+    /// `zonecfg export` output as captured
+    Export,
+    /// `zonecfg info` parsed into properties and resources
+    Json,
 }
 
-fn get_zonecfg(zone: &str) -> Result<Vec<u8>, anyhow::Error> {
-    let zonecfg = Command::new(ZONECFG)
-        .env_clear()
-        .args(["-z", zone, "info"])
-        .output()?;
+#[derive(Subcommand)]
+enum Cmd {
+    /// Snapshot all zone configs, commit the snapshot if anything changed and
+    /// prune old backups
+    Backup {
+        /// Report what would be written and pruned without changing outdir
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Also write the zone change report as JSON to FILE, "-" for stdout
+        #[arg(long, value_name = "FILE")]
+        report: Option<PathBuf>,
+    },
+    /// Remove backups beyond the configured retention
+    Prune {
+        /// Log the backups that would be removed without removing them
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+    },
+    /// List the backups in outdir with their time, size and zones
+    List,
+    /// Print a zone's config from a snapshot
+    Show {
+        /// Snapshot to read: a timestamp, an archive path or "latest"
+        #[arg(long)]
+        at: Option<String>,
+        /// What to print
+        #[arg(long, value_enum, default_value_t = ShowFormat::Info)]
+        format: ShowFormat,
+        /// Zone to show
+        zone: String,
+    },
+    /// Show how zone configs differ between two snapshots
+    Diff {
+        /// Older snapshot: a timestamp or archive path, defaults to the one
+        /// before "latest"
+        old: Option<String>,
+        /// Newer snapshot: a timestamp, an archive path or "latest" (default)
+        new: Option<String>,
+        /// Report property and resource changes instead of a line diff
+        #[arg(long)]
+        semantic: bool,
+    },
+    /// Show when a zone appeared, changed and disappeared across all backups
+    History {
+        /// Zone to trace
+        zone: String,
+    },
+    /// Search the zone configs in every backup for a regular expression
+    Grep {
+        /// Only search the backup "latest" points at
+        #[arg(long)]
+        latest: bool,
+        /// Only search this zone, may be given more than once
+        #[arg(short, long = "zone", value_name = "ZONE")]
+        zones: Vec<String>,
+        /// Regular expression to match lines against
+        pattern: Regex,
+    },
+    /// Recreate zones from the export data stored in a snapshot
+    Restore {
+        /// Snapshot to restore from: a timestamp, an archive path or "latest"
+        #[arg(long)]
+        at: Option<String>,
+        /// Overwrite zones that are already configured
+        #[arg(long)]
+        force: bool,
+        /// Print the zonecfg commands instead of running them
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+        /// Zones to restore
+        #[arg(required = true)]
+        zones: Vec<String>,
+    },
+    /// Check every backup in outdir against its checksum and manifest, and
+    /// that "latest" points at one of them
+    Verify,
+}
 
-    if !zonecfg.status.success() {
-        let stderr = String::from_utf8_lossy(&zonecfg.stderr);
-        bail!("exec {ZONECFG}: failed {:?} -- {stderr}", zonecfg.status);
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), anyhow::Error> {
+    let json = serde_json::to_string_pretty(value)?;
+    if path == Path::new("-") {
+        println!("{json}");
+        return Ok(());
     }
 
-    if zonecfg.stdout.is_empty() {
-        bail!("no zonecfg info for {zone}?");
+    fs::write(path, json + "\n").with_context(|| format!("{path:?}"))
+}
+
+/// Write what a backup committed to `report` and, for `-o json`, stdout.
+fn write_commits(
+    report: Option<&Path>,
+    output: OutputFormat,
+    commits: &Commits,
+) -> Result<(), anyhow::Error> {
+    let mut result = Ok(());
+    if let Some(report) = report {
+        result = write_json(report, commits);
+    }
+    if output == OutputFormat::Json {
+        result = result.and(write_json(Path::new("-"), commits));
     }
 
-    Ok(zonecfg.stdout)
+    result
 }
 
-fn find_zones() -> Result<Vec<String>, anyhow::Error> {
-    let zoneadm = Command::new(ZONEADM)
-        .env_clear()
-        .args(["list", "-n", "-c"])
-        .output()?;
+/// Format a backup's time for tables, falling back to its path when the time
+/// is unknown.
+fn display_time(timestamp: Option<DateTime<Utc>>, path: &Path) -> String {
+    match timestamp {
+        Some(ts) => ts.format("%Y-%m-%d %H:%M:%S").to_string(),
+        None => path.display().to_string(),
+    }
+}
 
-    if !zoneadm.status.success() {
-        bail!("exec {ZONEADM}: failed {:?}", zoneadm.status);
+fn list(ctx: &Ctx, output: OutputFormat) -> Result<(), anyhow::Error> {
+    let backups = summarize_snapshots(ctx)?;
+    if output == OutputFormat::Json {
+        return write_json(Path::new("-"), &backups);
     }
 
-    zoneadm
-        .stdout
-        .lines()
-        .collect::<Result<Vec<String>, _>>()
-        .context("failed to parse zoneadm output")
+    let mut out = io::stdout().lock();
+    writeln!(
+        out,
+        "  {:<20} {:>10} {:>5}  ZONES",
+        "TIMESTAMP", "SIZE", "COUNT"
+    )?;
+    for backup in backups {
+        let mark = if backup.latest { "*" } else { " " };
+        let timestamp = display_time(backup.timestamp, &backup.path);
+        let (count, names) = match &backup.zones {
+            Some(zones) => (zones.len().to_string(), zones.join(",")),
+            None => ("-".to_string(), "(unreadable)".to_string()),
+        };
+        writeln!(
+            out,
+            "{mark} {timestamp:<20} {:>10} {count:>5}  {names}",
+            backup.size
+        )?;
+    }
+
+    Ok(())
 }
 
-fn find_latest_snapshot(c: &Ctx) -> Result<Option<PathBuf>, anyhow::Error> {
-    let latest = file_latest(c);
-    if Path::exists(&latest) {
-        return fs::read_link(&latest).map(Some).map_err(From::from);
+fn show(ctx: &Ctx, at: Option<&str>, zone: &str, format: ShowFormat) -> Result<(), anyhow::Error> {
+    let snapshot = resolve_snapshot(ctx, at)?;
+    let member = match format {
+        ShowFormat::Export => Representation::Export,
+        ShowFormat::Info | ShowFormat::Json => Representation::Info,
+    }
+    .member_name(zone);
+
+    if format == ShowFormat::Json {
+        let info = match archive::read_member(&snapshot, &member)? {
+            Some(info) => info,
+            None => bail!("{snapshot:?} has no {member}, was info capture enabled?"),
+        };
+        let config = ZoneConfig::parse(&info).with_context(|| format!("parsing {member}"))?;
+        return write_json(Path::new("-"), &config);
     }
 
-    Ok(None)
+    if !archive::copy_member(&snapshot, &member, io::stdout().lock())? {
+        bail!("{snapshot:?} has no {member}");
+    }
+
+    Ok(())
 }
 
-fn snapshot_zone_configs(c: &Ctx) -> Result<tempfile::NamedTempFile, anyhow::Error> {
-    let zones = find_zones()?;
-    let tempfile = tempfile::NamedTempFile::new_in(&c.config.outdir)?;
-    let level = c
-        .config
-        .compression_level
-        .unwrap_or(DEFAULT_COMPRESSION_LEVEL);
-    let mut encoder = zstd::Encoder::new(tempfile, level)?;
-    {
-        let mut a = tar::Builder::new(&mut encoder);
-        for zone in zones {
-            match get_zonecfg(&zone) {
-                Ok(info) => {
-                    let mut header = Header::new_gnu();
-                    header.set_size(info.len() as u64);
-                    header.set_cksum();
-                    a.append_data(&mut header, format!("{zone}.zone"), info.as_slice())?;
-                    info!(&c.log, "appending zone {zone}");
+fn history(ctx: &Ctx, zone: &str, output: OutputFormat) -> Result<(), anyhow::Error> {
+    let history = zone_history(ctx, zone)?;
+    if output == OutputFormat::Json {
+        return write_json(Path::new("-"), &history);
+    }
+    if history.is_empty() {
+        bail!("zone {zone} is not in any backup");
+    }
+
+    let mut out = io::stdout().lock();
+    for entry in history {
+        let time = display_time(entry.timestamp, &entry.archive);
+        match entry.event {
+            Event::Appeared => writeln!(out, "{time} {zone} appeared")?,
+            Event::Disappeared => writeln!(out, "{time} {zone} disappeared")?,
+            Event::Changed { changes } if changes.is_empty() => {
+                writeln!(out, "{time} {zone} changed")?
+            }
+            Event::Changed { changes } => {
+                writeln!(out, "{time} {zone} changed:")?;
+                for change in changes {
+                    writeln!(out, "    {change}")?;
                 }
-                // perhaps the zone no longer exists, let's log an error and move on
-                Err(e) => warn!(c.log, "no info for {zone}: {e:?}"),
             }
         }
-        a.finish()?;
     }
 
-    encoder.finish().map_err(From::from)
+    Ok(())
 }
 
-fn generate_hash<R: Read>(mut input: R) -> Result<String, anyhow::Error> {
-    use std::fmt::Write;
-
-    let mut hasher = Sha256::new();
-    let mut out = String::new();
+fn grep(
+    ctx: &Ctx,
+    pattern: &Regex,
+    opts: GrepOptions,
+    output: OutputFormat,
+) -> Result<(), anyhow::Error> {
+    let matches = grep_snapshots(ctx, pattern, &opts)?;
+    if output == OutputFormat::Json {
+        return write_json(Path::new("-"), &matches);
+    }
 
-    io::copy(&mut input, &mut hasher)?;
-    for byte in hasher.finalize().iter() {
-        let _ = write!(out, "{byte:02x}");
+    let mut out = io::stdout().lock();
+    for m in matches {
+        let archive = m.archive.file_name().unwrap_or(m.archive.as_os_str());
+        writeln!(out, "{}:{}:{}", archive.to_string_lossy(), m.zone, m.line)?;
     }
 
-    Ok(out)
+    Ok(())
 }
 
-fn try_commit_zone_snapshot(
-    c: &Ctx,
-    snapshot: tempfile::NamedTempFile,
-) -> Result<(), anyhow::Error> {
-    let prefix = file_prefix(c);
-    let now = Utc::now().timestamp();
-    let path = PathBuf::from(&c.config.outdir).join(format!("{prefix}_{now}.zones.tar.zst"));
-    let latest_path = c.config.outdir.join(format!("{prefix}_latest"));
-
-    if let Some(latest) = find_latest_snapshot(c)? {
-        let latest_file = File::open(&latest).with_context(|| format!("{latest:?}"))?;
-        let latest_hash = generate_hash(&latest_file)?;
-        // FIXME: why does generate_hash(snapshot.as_file()) differ from opening the file?
-        let snapshot_file = File::open(snapshot.path())?;
-        let snapshot_hash = generate_hash(snapshot_file)?;
-
-        // As I understand it, zstd is deterministic in is compression output under the following conditions:
-        // - zstd version does not change
-        // - compression level does not change
-        // If either of these conditions change in practice, the tool will simply just write a new backup file to disk.
-        if latest_hash == snapshot_hash {
-            info!(
-                &c.log,
-                "No changes in zone configs detected, skipping write."
-            );
-
-            return Ok(());
+#[derive(Serialize)]
+struct VerifyEntry {
+    path: PathBuf,
+    #[serde(flatten)]
+    verified: Option<Verified>,
+    error: Option<String>,
+}
+
+fn verify(destinations: &[Destination], output: OutputFormat) -> Result<(), anyhow::Error> {
+    let mut entries = Vec::new();
+    let mut latest = Ok(());
+    for d in destinations {
+        let found: Vec<VerifyEntry> = find_snapshots(&d.ctx)?
+            .into_iter()
+            .map(|path| match verify_archive(&path) {
+                Ok(verified) => VerifyEntry {
+                    path,
+                    verified: Some(verified),
+                    error: None,
+                },
+                Err(e) => VerifyEntry {
+                    path,
+                    verified: None,
+                    error: Some(format!("{e:#}")),
+                },
+            })
+            .collect();
+        // an empty outdir has no latest to check, unless the link was left
+        // behind
+        if !found.is_empty() || fs::symlink_metadata(file_latest(&d.ctx)).is_ok() {
+            latest = latest.and(verify_latest(&d.ctx).map(|_| ()));
+        }
+        entries.extend(found);
+    }
+
+    if output == OutputFormat::Json {
+        write_json(Path::new("-"), &entries)?;
+    } else {
+        let mut out = io::stdout().lock();
+        for entry in &entries {
+            match (&entry.verified, &entry.error) {
+                (_, Some(error)) => writeln!(out, "FAILED {}: {error}", entry.path.display())?,
+                (Some(verified), None) => {
+                    let checksum = if verified.checksum { "ok" } else { "none" };
+                    let manifest = if verified.manifest { "ok" } else { "none" };
+                    writeln!(
+                        out,
+                        "ok {} ({} members, checksum {checksum}, manifest {manifest})",
+                        entry.path.display(),
+                        verified.members
+                    )?
+                }
+                (None, None) => unreachable!(),
+            }
         }
     }
 
-    snapshot
-        .persist(&path)
-        .with_context(|| format!("{path:?}"))?;
-    info!(&c.log, "zone backup file written to {path:?}");
-    let _ = fs::remove_file(&latest_path);
-    std::os::unix::fs::symlink(&path, &latest_path)
-        .with_context(|| format!("symlink {path:?} -> {latest_path:?}"))?;
-    info!(&c.log, "symlinked {path:?} to {latest_path:?}");
+    let failed = entries.iter().filter(|e| e.error.is_some()).count();
+    if failed > 0 {
+        bail!("{failed} of {} backups failed verification", entries.len());
+    }
 
-    Ok(())
+    latest
 }
 
-fn prune_zonecfg_backups(c: &Ctx) -> Result<(), anyhow::Error> {
-    let prefix = file_prefix(c);
-    let latest = file_latest(c);
-    let latest_file = match latest.file_name() {
-        Some(name) => name,
-        None => bail!("latest filename should be known"),
-    };
-    // find all entries in the directory, stopping on first error
-    let mut ents = read_dir(&c.config.outdir)
-        .with_context(|| format!("reading {:?}", c.config.outdir))?
-        .collect::<Result<Vec<_>, _>>()?;
-    // keep entries that start with our prefix
-    let filter = |f: &str| -> bool { f.starts_with(prefix) && f != latest_file };
-    ents.retain(|e| {
-        e.file_name()
-            .as_os_str()
-            .to_str()
-            .map(filter)
-            .unwrap_or(false)
-    });
-    // sort them so that we delete the oldest backups
-    ents.sort_by_key(|e| Reverse(e.file_name()));
-
-    if c.config.number_of_backups < ents.len() {
-        for to_remove in &ents[c.config.number_of_backups..] {
-            let f = to_remove.file_name();
-            let path = c.config.outdir.join(f);
-            fs::remove_file(&path).with_context(|| format!("removing file {path:?}"))?;
-            info!(&c.log, "pruned {path:?}")
+fn run(
+    destinations: &[Destination],
+    command: Cmd,
+    output: OutputFormat,
+) -> Result<(), anyhow::Error> {
+    if output == OutputFormat::Json {
+        let text_only = match &command {
+            Cmd::Prune { .. } => Some("prune"),
+            Cmd::Diff {
+                semantic: false, ..
+            } => Some("diff without --semantic"),
+            Cmd::Restore { .. } => Some("restore"),
+            _ => None,
+        };
+        if let Some(command) = text_only {
+            bail!("-o json is not supported by {command}");
         }
     }
 
-    Ok(())
+    // commands that only read look at the first destination
+    let ctx = &destinations[0].ctx;
+    match command {
+        Cmd::Backup {
+            dry_run: false,
+            report,
+        } => run::backup(destinations, &CommandSource, |commits| {
+            write_commits(report.as_deref(), output, commits)
+        }),
+        Cmd::Backup {
+            dry_run: true,
+            report,
+        } => run::backup_dry_run(destinations, &CommandSource, |commits| {
+            write_commits(report.as_deref(), output, commits)
+        }),
+        Cmd::Prune { dry_run: false } => run::prune(destinations),
+        Cmd::Prune { dry_run: true } => run::prune_dry_run(destinations),
+        Cmd::List => list(ctx, output),
+        Cmd::Show { at, format, zone } => show(ctx, at.as_deref(), &zone, format),
+        Cmd::Diff { old, new, semantic } => {
+            let old = match old {
+                Some(old) => resolve_snapshot(ctx, Some(&old))?,
+                None => match find_previous_snapshot(ctx)? {
+                    Some(previous) => previous,
+                    None => bail!("no snapshot before latest to compare against"),
+                },
+            };
+            let new = resolve_snapshot(ctx, new.as_deref())?;
+            match output {
+                OutputFormat::Json => write_json(Path::new("-"), &semantic_diff(&old, &new)?),
+                OutputFormat::Text => {
+                    diff_snapshots(&old, &new, semantic, io::stdout()).map(|_| ())
+                }
+            }
+        }
+        Cmd::History { zone } => history(ctx, &zone, output),
+        Cmd::Grep {
+            latest,
+            zones,
+            pattern,
+        } => grep(ctx, &pattern, GrepOptions { latest, zones }, output),
+        Cmd::Restore {
+            at,
+            force,
+            dry_run,
+            zones,
+        } => {
+            let snapshot = resolve_snapshot(ctx, at.as_deref())?;
+            let opts = RestoreOptions { force, dry_run };
+            restore_zones(ctx, &CommandSource, &snapshot, &zones, opts, io::stdout())
+        }
+        Cmd::Verify => verify(destinations, output),
+    }
 }
 
 fn main() -> Result<(), anyhow::Error> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        bail!("usage: {} config_file", args[0]);
-    }
-    let ctx = Ctx {
-        config: Config::from_file(&args[1])?,
-        log: create_logger(),
+    let args = Args::parse();
+    let config = match args.config.or(args.legacy_config) {
+        Some(config) => config,
+        None => bail!("no config file given, see --config"),
     };
+    let config = Config::from_file(&config)?;
+    let log = create_logger(args.log_level.into(), args.output == OutputFormat::Json);
+
+    let destinations = run::destinations(&config, &log, args.destination.as_deref())?;
 
-    if let Some(level) = ctx.config.compression_level {
-        if !(1..=21).contains(&level) {
-            bail!("compression level must be between 1-21");
+    for d in &destinations {
+        if let Some(level) = d.ctx.config.settings.compression_level {
+            if !(1..=21).contains(&level) {
+                bail!("compression level must be between 1-21");
+            }
         }
+        Limits::from_config(&d.ctx.config)?;
     }
+    config.lock_timeout()?;
+    config.hooks.timeout()?;
+    install_signal_handler()?;
 
-    let snapshot = snapshot_zone_configs(&ctx)?;
-    try_commit_zone_snapshot(&ctx, snapshot)?;
-    prune_zonecfg_backups(&ctx)?;
+    if config.capture().is_empty() {
+        bail!("capture must list at least one of \"info\" or \"export\"");
+    }
 
-    Ok(())
+    let command = args.command.unwrap_or(Cmd::Backup {
+        dry_run: false,
+        report: None,
+    });
+    run(&destinations, command, args.output)
 }
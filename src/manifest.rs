@@ -0,0 +1,77 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{archive, source::ZoneInfo};
+
+/// Name of the manifest member, always the first entry in an archive.
+pub const MANIFEST_NAME: &str = "MANIFEST.json";
+
+/// Member SHA-256s keyed by member name, keyed by zone name.
+pub type ZoneHashes = BTreeMap<String, BTreeMap<String, String>>;
+
+/// Describes where and when a snapshot archive was captured and what it holds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Manifest {
+    pub hostname: String,
+    pub captured_at: DateTime<Utc>,
+    pub tool_version: String,
+    pub prefix: String,
+    pub zones: Vec<ZoneEntry>,
+    /// Zones that were found but could not be captured.
+    pub failed: Vec<FailedZone>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ZoneEntry {
+    #[serde(flatten)]
+    pub info: ZoneInfo,
+    /// SHA-256 of each archive member holding this zone, keyed by member name.
+    pub members: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FailedZone {
+    pub name: String,
+    pub error: String,
+}
+
+impl Manifest {
+    pub fn new(prefix: &str, captured_at: DateTime<Utc>) -> Result<Self, anyhow::Error> {
+        let hostname = hostname::get()
+            .context("failed to determine hostname")?
+            .to_string_lossy()
+            .into_owned();
+
+        Ok(Self {
+            hostname,
+            captured_at,
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            prefix: prefix.to_string(),
+            zones: Vec::new(),
+            failed: Vec::new(),
+        })
+    }
+
+    /// SHA-256 of every zone member in the archive, grouped by zone.
+    pub fn zone_hashes(&self) -> ZoneHashes {
+        self.zones
+            .iter()
+            .map(|z| (z.info.name.clone(), z.members.clone()))
+            .collect()
+    }
+
+    /// Read the manifest out of a snapshot archive. Archives written before
+    /// manifests were introduced yield `None`.
+    pub fn from_archive<P: AsRef<Path>>(path: P) -> Result<Option<Self>, anyhow::Error> {
+        let path = path.as_ref();
+        match archive::read_member(path, MANIFEST_NAME)? {
+            Some(data) => serde_json::from_slice(&data)
+                .with_context(|| format!("parsing {MANIFEST_NAME} in {path:?}"))
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+}
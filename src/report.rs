@@ -0,0 +1,84 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::Context;
+use serde::Serialize;
+use slog::{info, Logger};
+
+use crate::{
+    archive,
+    manifest::ZoneHashes,
+    semantic::{diff_info, Change},
+    source::Representation,
+};
+
+/// Which zones differ between two snapshots.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ChangeReport {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub modified: Vec<String>,
+    /// Property and resource level changes for modified zones, when their
+    /// `zonecfg info` output was captured.
+    pub details: BTreeMap<String, Vec<Change>>,
+}
+
+impl ChangeReport {
+    /// Compare zones by the hashes of their archive members. A zone whose set
+    /// of captured representations changed counts as modified.
+    pub fn between(previous: &ZoneHashes, current: &ZoneHashes) -> Self {
+        let mut report = Self::default();
+        for (zone, members) in current {
+            match previous.get(zone) {
+                None => report.added.push(zone.clone()),
+                Some(prev) if prev != members => report.modified.push(zone.clone()),
+                Some(_) => (),
+            }
+        }
+        report.removed = previous
+            .keys()
+            .filter(|zone| !current.contains_key(*zone))
+            .cloned()
+            .collect();
+
+        report
+    }
+
+    /// Fill in `details` for each modified zone by comparing the `zonecfg info`
+    /// members of the `previous` and `current` snapshot archives.
+    pub fn describe(&mut self, previous: &Path, current: &Path) -> Result<(), anyhow::Error> {
+        let before = archive::read_members(previous)?;
+        let after = archive::read_members(current)?;
+        for zone in &self.modified {
+            let member = Representation::Info.member_name(zone);
+            if let (Some(old), Some(new)) = (before.get(&member), after.get(&member)) {
+                let changes = diff_info(old, new).with_context(|| member.clone())?;
+                self.details.insert(zone.clone(), changes);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    pub fn log(&self, log: &Logger) {
+        for zone in &self.added {
+            info!(log, "zone {zone} added");
+        }
+        for zone in &self.removed {
+            info!(log, "zone {zone} removed");
+        }
+        for zone in &self.modified {
+            match self.details.get(zone) {
+                Some(changes) if !changes.is_empty() => {
+                    for change in changes {
+                        info!(log, "zone {zone} modified: {change}");
+                    }
+                }
+                _ => info!(log, "zone {zone} modified"),
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use std::{io::Write, path::Path};
+
+use anyhow::{bail, Context};
+use slog::info;
+
+use crate::{
+    archive,
+    source::{Representation, ZoneSource, ZONECFG},
+    Ctx,
+};
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RestoreOptions {
+    /// Overwrite zones that are already configured.
+    pub force: bool,
+    /// Print the zonecfg invocation and script rather than running it.
+    pub dry_run: bool,
+}
+
+/// Turn stored `zonecfg export` output into a script suitable for
+/// `zonecfg -z ZONE -f`.
+///
+/// When forcing, the leading `create` is given `-F` so that zonecfg replaces
+/// an existing configuration instead of prompting.
+pub fn restore_script(export: &[u8], force: bool) -> Result<String, anyhow::Error> {
+    let export = std::str::from_utf8(export).context("export data is not valid utf-8")?;
+    let mut script = String::with_capacity(export.len() + 4);
+    for (i, line) in export.lines().enumerate() {
+        match line.strip_prefix("create") {
+            Some(rest) if i == 0 && force => {
+                script.push_str("create -F");
+                script.push_str(rest);
+            }
+            _ => script.push_str(line),
+        }
+        script.push('\n');
+    }
+
+    Ok(script)
+}
+
+/// Recreate `zones` from the export data stored in `snapshot`.
+///
+/// Every requested zone is checked before anything is applied, so a missing
+/// member or an already configured zone leaves the system untouched. With
+/// `dry_run` set, the commands are written to `out` instead of being run.
+pub fn restore_zones<S: ZoneSource + ?Sized, W: Write>(
+    c: &Ctx,
+    source: &S,
+    snapshot: &Path,
+    zones: &[String],
+    opts: RestoreOptions,
+    mut out: W,
+) -> Result<(), anyhow::Error> {
+    let existing = source.find_zones()?;
+    let conflicts: Vec<&str> = zones
+        .iter()
+        .filter(|z| existing.iter().any(|e| &e.name == *z))
+        .map(String::as_str)
+        .collect();
+    if !opts.force && !conflicts.is_empty() {
+        bail!(
+            "refusing to overwrite existing zones: {} (use --force)",
+            conflicts.join(", ")
+        );
+    }
+
+    let mut scripts = Vec::with_capacity(zones.len());
+    for zone in zones {
+        let member = Representation::Export.member_name(zone);
+        let export = match archive::read_member(snapshot, &member)? {
+            Some(export) => export,
+            None => bail!("{snapshot:?} has no {member}, was export capture enabled?"),
+        };
+        let script = restore_script(&export, opts.force)
+            .with_context(|| format!("{member} in {snapshot:?}"))?;
+        scripts.push((zone, script));
+    }
+
+    for (zone, script) in scripts {
+        if opts.dry_run {
+            writeln!(out, "{ZONECFG} -z {zone} -f - <<'EOF'\n{script}EOF")?;
+            continue;
+        }
+        source
+            .import_zonecfg(zone, script.as_bytes())
+            .with_context(|| format!("restoring {zone}"))?;
+        info!(&c.log, "restored zone {zone} from {snapshot:?}");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,190 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use serde::Serialize;
+
+use crate::config::Config;
+
+/// How many backups to keep, overall and per calendar period.
+///
+/// Periods are grandfather-father-son style: for each of the `hourly`,
+/// `daily`, ... limits, the newest backup of each of that many most recent
+/// periods that have a backup is kept. Periods are in UTC, like the
+/// timestamps in backup file names.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    pub last: Option<usize>,
+    pub hourly: Option<usize>,
+    pub daily: Option<usize>,
+    pub weekly: Option<usize>,
+    pub monthly: Option<usize>,
+    pub yearly: Option<usize>,
+}
+
+/// Why a backup is kept.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Keep {
+    Last,
+    Hourly,
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+    /// No count based retention is configured.
+    Unlimited,
+    /// `_latest` points at it.
+    Latest,
+}
+
+impl Keep {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Keep::Last => "last",
+            Keep::Hourly => "hourly",
+            Keep::Daily => "daily",
+            Keep::Weekly => "weekly",
+            Keep::Monthly => "monthly",
+            Keep::Yearly => "yearly",
+            Keep::Unlimited => "unlimited",
+            Keep::Latest => "latest",
+        }
+    }
+}
+
+/// The calendar period a backup falls in, comparable within one `Keep` kind.
+fn period(kind: Keep, time: &DateTime<Utc>) -> (i32, u32, u32, u32) {
+    match kind {
+        Keep::Hourly => (time.year(), time.month(), time.day(), time.hour()),
+        Keep::Daily => (time.year(), time.month(), time.day(), 0),
+        Keep::Weekly => {
+            let week = time.iso_week();
+            (week.year(), week.week(), 0, 0)
+        }
+        Keep::Monthly => (time.year(), time.month(), 0, 0),
+        Keep::Yearly => (time.year(), 0, 0, 0),
+        Keep::Last | Keep::Unlimited | Keep::Latest => unreachable!(),
+    }
+}
+
+impl Retention {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            last: config.settings.keep_last,
+            hourly: config.settings.keep_hourly,
+            daily: config.settings.keep_daily,
+            weekly: config.settings.keep_weekly,
+            monthly: config.settings.keep_monthly,
+            yearly: config.settings.keep_yearly,
+        }
+    }
+
+    /// Whether no count is configured at all, in which case every backup is
+    /// kept.
+    pub fn is_unlimited(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Decide which backups to keep. `times` must be ordered newest first,
+    /// backups with an unknown time only count towards `last`.
+    ///
+    /// Returns the first reason each backup is kept for, or `None` for those
+    /// that can be pruned.
+    pub fn apply(&self, times: &[Option<DateTime<Utc>>]) -> Vec<Option<Keep>> {
+        if self.is_unlimited() {
+            return vec![Some(Keep::Unlimited); times.len()];
+        }
+
+        let mut keep: Vec<Option<Keep>> = times
+            .iter()
+            .enumerate()
+            .map(|(i, _)| (i < self.last.unwrap_or(0)).then_some(Keep::Last))
+            .collect();
+
+        for (kind, count) in [
+            (Keep::Hourly, self.hourly),
+            (Keep::Daily, self.daily),
+            (Keep::Weekly, self.weekly),
+            (Keep::Monthly, self.monthly),
+            (Keep::Yearly, self.yearly),
+        ] {
+            let count = count.unwrap_or(0);
+            let mut seen = Vec::new();
+            for (i, time) in times.iter().enumerate() {
+                if seen.len() == count {
+                    break;
+                }
+                let period = match time {
+                    Some(time) => period(kind, time),
+                    None => continue,
+                };
+                if !seen.contains(&period) {
+                    seen.push(period);
+                    keep[i].get_or_insert(kind);
+                }
+            }
+        }
+
+        keep
+    }
+}
+
+/// Limits on how old and how large the kept backups may get, overruling
+/// [`Retention`].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_age: Option<Duration>,
+    pub max_total_size: Option<u64>,
+}
+
+impl Limits {
+    pub fn from_config(config: &Config) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            max_age: config.settings.max_age()?,
+            max_total_size: config.settings.max_total_size()?,
+        })
+    }
+
+    /// Stop keeping backups that are older than `max_age` at `now`, and the
+    /// oldest ones once the kept backups add up to more than
+    /// `max_total_size`. `backups` holds each backup's time and size, newest
+    /// first, in the same order as `keep`.
+    ///
+    /// The backup at index `latest` is always kept and counts towards the
+    /// size first.
+    pub fn apply(
+        &self,
+        now: DateTime<Utc>,
+        backups: &[(Option<DateTime<Utc>>, u64)],
+        latest: Option<usize>,
+        keep: &mut [Option<Keep>],
+    ) {
+        let oldest = self
+            .max_age
+            .and_then(|age| chrono::Duration::from_std(age).ok())
+            .and_then(|age| now.checked_sub_signed(age));
+        let mut total = 0;
+        if let Some(latest) = latest {
+            keep[latest] = Some(Keep::Latest);
+            total = backups[latest].1;
+        }
+
+        let mut full = false;
+        for (i, (time, size)) in backups.iter().enumerate() {
+            if keep[i].is_none() || Some(i) == latest {
+                continue;
+            }
+            if matches!((oldest, time), (Some(oldest), Some(time)) if time < &oldest) {
+                keep[i] = None;
+                continue;
+            }
+            if let Some(max) = self.max_total_size {
+                full |= total + size > max;
+            }
+            match full {
+                true => keep[i] = None,
+                false => total += size,
+            }
+        }
+    }
+}
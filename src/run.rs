@@ -0,0 +1,326 @@
+use std::env;
+
+use anyhow::bail;
+use serde::{Serialize, Serializer};
+use slog::{debug, error, info, o, Logger};
+
+use crate::{
+    capture_zone_configs,
+    cleanup::{remove_stale_tempfiles, STALE_TEMP_AGE},
+    config::Config,
+    find_prunable,
+    hooks::{change_vars, run_hook, Hook},
+    lock::RunLock,
+    plan_commit, prune_zonecfg_backups,
+    source::ZoneSource,
+    try_commit_zone_snapshot, Commit, Ctx,
+};
+
+/// A configured destination and the context to run against it.
+pub struct Destination {
+    pub name: String,
+    pub ctx: Ctx,
+}
+
+/// The destinations `config` declares, or only the one named `only`. With
+/// several, their log messages name the destination.
+pub fn destinations(
+    config: &Config,
+    log: &Logger,
+    only: Option<&str>,
+) -> Result<Vec<Destination>, anyhow::Error> {
+    let mut destinations = config.destinations()?;
+    if let Some(name) = only {
+        destinations.retain(|(n, _)| n == name);
+        if destinations.is_empty() {
+            bail!("no destination named {name:?}");
+        }
+    }
+
+    let several = destinations.len() > 1;
+    Ok(destinations
+        .into_iter()
+        .map(|(name, config)| {
+            let log = match several {
+                true => log.new(o!("destination" => name.clone())),
+                false => log.clone(),
+            };
+            Destination {
+                name,
+                ctx: Ctx { config, log },
+            }
+        })
+        .collect())
+}
+
+/// What a backup committed, or would commit, to each destination that didn't
+/// fail. Serializes as the commit alone when the backup ran against a single
+/// destination, otherwise as an array naming the destination of each.
+pub struct Commits<'a> {
+    /// How many destinations the backup ran against.
+    total: usize,
+    pub commits: Vec<(&'a Destination, Commit)>,
+}
+
+#[derive(Serialize)]
+struct ForDestination<'a> {
+    destination: &'a str,
+    #[serde(flatten)]
+    commit: &'a Commit,
+}
+
+impl Serialize for Commits<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if let (1, [(_, commit)]) = (self.total, self.commits.as_slice()) {
+            return commit.serialize(serializer);
+        }
+
+        serializer.collect_seq(self.commits.iter().map(|(d, commit)| ForDestination {
+            destination: &d.name,
+            commit,
+        }))
+    }
+}
+
+/// Fold the errors of running against each of `total` destinations into one.
+/// A single destination's error is returned as is.
+fn destination_errors(
+    total: usize,
+    mut errors: Vec<(&Destination, anyhow::Error)>,
+) -> Result<(), anyhow::Error> {
+    if total == 1 {
+        return errors.pop().map_or(Ok(()), |(_, e)| Err(e));
+    }
+    for (d, e) in &errors {
+        error!(d.ctx.log, "{e:#}");
+    }
+
+    match errors.len() {
+        0 => Ok(()),
+        failed => bail!("{failed} of {total} destinations failed"),
+    }
+}
+
+/// Lock the outdir of a destination unless one of `locks` already covers it,
+/// and remove stale temporary files left there.
+fn lock_destination(ctx: &Ctx, locks: &mut Vec<RunLock>) -> Result<(), anyhow::Error> {
+    let outdir = &ctx.config.outdir;
+    // destinations may share an outdir, and locking it twice would block
+    if !locks.iter().any(|l| l.path().parent() == Some(outdir)) {
+        let lock = RunLock::acquire(outdir, ctx.config.lock_timeout()?)?;
+        debug!(ctx.log, "locked {:?}", lock.path());
+        locks.push(lock);
+    }
+
+    remove_stale_tempfiles(ctx, STALE_TEMP_AGE)
+}
+
+/// Run the on_error hook if `result` is an error. A failing on_error hook is
+/// only logged.
+fn on_error(c: &Ctx, result: Result<(), anyhow::Error>) -> Result<(), anyhow::Error> {
+    if let Err(e) = &result {
+        let vars = [("ZCB_ERROR", format!("{e:#}"))];
+        if let Err(e) = run_hook(c, Hook::OnError, &vars) {
+            error!(c.log, "{e:#}");
+        }
+    }
+
+    result
+}
+
+/// Capture the zones from `source` once, commit them to every destination
+/// whose `_latest` differs and prune each destination, running the hooks
+/// along the way. `report` gets the commits before any post_commit hook runs.
+///
+/// Every destination is locked before pre_backup runs. One that can't be
+/// locked, committed to or pruned doesn't stop the others, and neither does a
+/// failing `report` stop the hooks and pruning.
+pub fn backup<S, F>(
+    destinations: &[Destination],
+    source: &S,
+    report: F,
+) -> Result<(), anyhow::Error>
+where
+    S: ZoneSource + ?Sized,
+    F: FnOnce(&Commits) -> Result<(), anyhow::Error>,
+{
+    let result = backup_locked(destinations, source, report);
+    on_error(&destinations[0].ctx, result)
+}
+
+fn backup_locked<S, F>(
+    destinations: &[Destination],
+    source: &S,
+    report: F,
+) -> Result<(), anyhow::Error>
+where
+    S: ZoneSource + ?Sized,
+    F: FnOnce(&Commits) -> Result<(), anyhow::Error>,
+{
+    // every lock is taken up front and held until the hooks and pruning are
+    // done, so overlapping runs don't even run pre_backup at the same time
+    let mut locks = Vec::new();
+    let mut locked = Vec::new();
+    let mut errors = Vec::new();
+    for d in destinations {
+        match lock_destination(&d.ctx, &mut locks) {
+            Ok(()) => locked.push(d),
+            Err(e) => errors.push((d, e)),
+        }
+    }
+    let first = match locked.first() {
+        Some(first) => &first.ctx,
+        None => return destination_errors(destinations.len(), errors),
+    };
+
+    // zones are captured once, each destination compares against its own
+    // _latest
+    let captured =
+        run_hook(first, Hook::PreBackup, &[]).and_then(|_| capture_zone_configs(first, source));
+    let capture = match captured {
+        Ok(capture) => capture,
+        Err(e) => {
+            for (d, e) in &errors {
+                error!(d.ctx.log, "{e:#}");
+            }
+            return Err(e);
+        }
+    };
+
+    let mut commits = Commits {
+        total: destinations.len(),
+        commits: Vec::new(),
+    };
+    for d in locked {
+        let commit = capture
+            .write_snapshot(&d.ctx)
+            .and_then(|snapshot| try_commit_zone_snapshot(&d.ctx, snapshot));
+        match commit {
+            Ok(commit) => commits.commits.push((d, commit)),
+            Err(e) => errors.push((d, e)),
+        }
+    }
+
+    // the archives are committed, so the hooks and pruning still run when the
+    // report can't be written
+    let reported = match commits.commits.is_empty() {
+        true => Ok(()),
+        false => report(&commits),
+    };
+    for (d, commit) in &commits.commits {
+        if let Err(e) = after_commit(&d.ctx, commit) {
+            errors.push((d, e));
+        }
+    }
+
+    match (reported, destination_errors(destinations.len(), errors)) {
+        (Err(report), Err(e)) => {
+            error!(first.log, "{report:#}");
+            Err(e)
+        }
+        (reported, result) => result.and(reported),
+    }
+}
+
+/// Run the hooks for `commit` and prune the destination it went to.
+fn after_commit(ctx: &Ctx, commit: &Commit) -> Result<(), anyhow::Error> {
+    let hooked = match &commit.archive {
+        Some(archive) => {
+            let mut vars = change_vars(&commit.changes);
+            vars.push(("ZCB_ARCHIVE", archive.display().to_string()));
+            run_hook(ctx, Hook::PostCommit, &vars)
+        }
+        None => run_hook(ctx, Hook::OnUnchanged, &[]),
+    };
+
+    // a failing hook must not hold up retention, so prune regardless
+    match (hooked, prune_destination(ctx)) {
+        (Err(hook), Err(prune)) => bail!("{hook:#}, and pruning failed too: {prune:#}"),
+        (hooked, pruned) => hooked.and(pruned),
+    }
+}
+
+/// Log what [`backup`] would write and prune without touching any outdir.
+/// `report` gets what would be committed.
+pub fn backup_dry_run<S, F>(
+    destinations: &[Destination],
+    source: &S,
+    report: F,
+) -> Result<(), anyhow::Error>
+where
+    S: ZoneSource + ?Sized,
+    F: FnOnce(&Commits) -> Result<(), anyhow::Error>,
+{
+    let capture = capture_zone_configs(&destinations[0].ctx, source)?;
+    let mut commits = Commits {
+        total: destinations.len(),
+        commits: Vec::new(),
+    };
+    for d in destinations {
+        let ctx = &d.ctx;
+        // stay out of outdir, we don't hold its lock
+        let snapshot = capture.write_snapshot_in(ctx, &env::temp_dir())?;
+        // the archive is written as is, so this is the size it would take up
+        let size = snapshot.file.as_file().metadata()?.len();
+        let commit = plan_commit(ctx, &snapshot)?;
+
+        let pending = match &commit.archive {
+            Some(path) => {
+                commit.changes.log(&ctx.log);
+                info!(ctx.log, "would write {path:?}");
+                Some((path.as_path(), size))
+            }
+            None => {
+                info!(
+                    ctx.log,
+                    "no changes in zone configs detected, nothing would be written"
+                );
+                None
+            }
+        };
+        for path in find_prunable(ctx, pending)? {
+            info!(ctx.log, "would prune {path:?}");
+        }
+        commits.commits.push((d, commit));
+    }
+
+    report(&commits)
+}
+
+/// Lock and prune each destination on its own, running post_prune for each.
+pub fn prune(destinations: &[Destination]) -> Result<(), anyhow::Error> {
+    let errors = destinations
+        .iter()
+        .filter_map(|d| {
+            let mut locks = Vec::new();
+            lock_destination(&d.ctx, &mut locks)
+                .and_then(|_| prune_destination(&d.ctx))
+                .err()
+                .map(|e| (d, e))
+        })
+        .collect();
+    let result = destination_errors(destinations.len(), errors);
+
+    on_error(&destinations[0].ctx, result)
+}
+
+fn prune_destination(ctx: &Ctx) -> Result<(), anyhow::Error> {
+    let pruned = prune_zonecfg_backups(ctx)?;
+    let pruned = pruned
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join(" ");
+    run_hook(ctx, Hook::PostPrune, &[("ZCB_PRUNED", pruned)])
+}
+
+/// Log the backups [`prune`] would remove from each destination.
+pub fn prune_dry_run(destinations: &[Destination]) -> Result<(), anyhow::Error> {
+    for d in destinations {
+        for path in find_prunable(&d.ctx, None)? {
+            info!(d.ctx.log, "would prune {path:?}");
+        }
+    }
+
+    Ok(())
+}
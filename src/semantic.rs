@@ -0,0 +1,132 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use anyhow::Context;
+use serde::Serialize;
+
+use crate::zonecfg::{Resource, ZoneConfig};
+
+/// A single difference between two zone configurations.
+///
+/// `path` names a global property (`autoboot`), a resource
+/// (`fs[dir=/data]`) or a resource property
+/// (`net[physical=vnic0].allowed-address`). Added and removed resources carry
+/// no value.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "change", rename_all = "lowercase")]
+pub enum Change {
+    Added {
+        path: String,
+        value: Option<String>,
+    },
+    Removed {
+        path: String,
+        value: Option<String>,
+    },
+    Changed {
+        path: String,
+        from: String,
+        to: String,
+    },
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Added { path, value: None } => write!(f, "{path} added"),
+            Change::Added {
+                path,
+                value: Some(v),
+            } => write!(f, "{path} added {v}"),
+            Change::Removed { path, value: None } => write!(f, "{path} removed"),
+            Change::Removed {
+                path,
+                value: Some(v),
+            } => write!(f, "{path} removed (was {v})"),
+            Change::Changed { path, from, to } => write!(f, "{path} changed {from} → {to}"),
+        }
+    }
+}
+
+/// Compare two property maps, naming each property `{prefix}{name}`.
+fn diff_properties<V: ToString + PartialEq>(
+    prefix: &str,
+    old: &BTreeMap<String, V>,
+    new: &BTreeMap<String, V>,
+    changes: &mut Vec<Change>,
+) {
+    let names: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for name in names {
+        let path = format!("{prefix}{name}");
+        match (old.get(name), new.get(name)) {
+            (Some(from), Some(to)) if from != to => changes.push(Change::Changed {
+                path,
+                from: from.to_string(),
+                to: to.to_string(),
+            }),
+            (Some(from), None) => changes.push(Change::Removed {
+                path,
+                value: Some(from.to_string()),
+            }),
+            (None, Some(to)) => changes.push(Change::Added {
+                path,
+                value: Some(to.to_string()),
+            }),
+            _ => (),
+        }
+    }
+}
+
+fn by_key(resources: &[Resource]) -> BTreeMap<String, Vec<&Resource>> {
+    let mut keyed: BTreeMap<String, Vec<&Resource>> = BTreeMap::new();
+    for resource in resources {
+        keyed.entry(resource.key()).or_default().push(resource);
+    }
+    keyed
+}
+
+/// Describe how `new` differs from `old` in terms of properties and
+/// resources.
+///
+/// Resources are matched up by their [`Resource::key`] rather than their
+/// position, so reordering them is not a change.
+pub fn diff_configs(old: &ZoneConfig, new: &ZoneConfig) -> Vec<Change> {
+    let mut changes = Vec::new();
+    diff_properties("", &old.properties, &new.properties, &mut changes);
+
+    let (old, new) = (by_key(&old.resources), by_key(&new.resources));
+    let keys: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+    for key in keys {
+        let before = old.get(key).map(Vec::as_slice).unwrap_or_default();
+        let after = new.get(key).map(Vec::as_slice).unwrap_or_default();
+        for i in 0..before.len().max(after.len()) {
+            match (before.get(i), after.get(i)) {
+                (Some(from), Some(to)) => {
+                    let prefix = format!("{key}.");
+                    diff_properties(&prefix, &from.properties, &to.properties, &mut changes);
+                }
+                (Some(_), None) => changes.push(Change::Removed {
+                    path: key.clone(),
+                    value: None,
+                }),
+                (None, Some(_)) => changes.push(Change::Added {
+                    path: key.clone(),
+                    value: None,
+                }),
+                (None, None) => unreachable!(),
+            }
+        }
+    }
+
+    changes
+}
+
+/// Parse two sets of `zonecfg info` output and describe how they differ.
+pub fn diff_info(old: &[u8], new: &[u8]) -> Result<Vec<Change>, anyhow::Error> {
+    let old = ZoneConfig::parse(old).context("parsing old zonecfg info")?;
+    let new = ZoneConfig::parse(new).context("parsing new zonecfg info")?;
+
+    Ok(diff_configs(&old, &new))
+}
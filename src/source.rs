@@ -0,0 +1,254 @@
+use std::{
+    collections::BTreeSet,
+    fmt, fs,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+const ZONEADM: &str = "/usr/sbin/zoneadm";
+pub const ZONECFG: &str = "/usr/sbin/zonecfg";
+
+/// A form of zone configuration that `zonecfg(8)` can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Representation {
+    /// Human readable `zonecfg -z ZONE info` output.
+    Info,
+    /// `zonecfg -z ZONE export` output, which can be replayed via `zonecfg -f`.
+    Export,
+}
+
+impl Representation {
+    pub const ALL: [Representation; 2] = [Representation::Info, Representation::Export];
+
+    /// The `zonecfg` subcommand producing this representation.
+    pub fn subcommand(&self) -> &'static str {
+        match self {
+            Representation::Info => "info",
+            Representation::Export => "export",
+        }
+    }
+
+    /// Extension used for this representation, both in a [`DirectorySource`]
+    /// and as tar members in a snapshot archive.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Representation::Info => "zone",
+            Representation::Export => "export",
+        }
+    }
+
+    /// Name of the tar member holding this representation of `zone`.
+    pub fn member_name(&self, zone: &str) -> String {
+        format!("{zone}.{}", self.extension())
+    }
+
+    /// Split a tar member name back into its zone and representation.
+    pub fn from_member_name(name: &str) -> Option<(&str, Representation)> {
+        let (zone, ext) = name.rsplit_once('.')?;
+        Representation::ALL
+            .into_iter()
+            .find(|r| r.extension() == ext)
+            .map(|r| (zone, r))
+    }
+}
+
+impl fmt::Display for Representation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.subcommand())
+    }
+}
+
+/// A configured zone as reported by `zoneadm list -p`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZoneInfo {
+    pub name: String,
+    pub state: Option<String>,
+    pub brand: Option<String>,
+    pub uuid: Option<String>,
+}
+
+impl ZoneInfo {
+    /// A zone we only know the name of.
+    pub fn named<S: Into<String>>(name: S) -> Self {
+        Self {
+            name: name.into(),
+            state: None,
+            brand: None,
+            uuid: None,
+        }
+    }
+
+    /// Parse one line of `zoneadm list -p` output, which looks like
+    /// `zoneid:zonename:state:zonepath:uuid:brand:ip-type[:...]`.
+    ///
+    /// Colons and backslashes within fields are escaped with a backslash.
+    pub fn from_parsable(line: &str) -> Result<Self, anyhow::Error> {
+        let mut fields = vec![String::new()];
+        let mut chars = line.chars();
+        while let Some(ch) = chars.next() {
+            match ch {
+                '\\' => fields.last_mut().unwrap().extend(chars.next()),
+                ':' => fields.push(String::new()),
+                _ => fields.last_mut().unwrap().push(ch),
+            }
+        }
+
+        if fields.len() < 6 {
+            bail!("malformed zoneadm output: {line:?}");
+        }
+        let field = |i: usize| Some(fields[i].clone()).filter(|f| !f.is_empty());
+
+        Ok(Self {
+            name: fields[1].clone(),
+            state: field(2),
+            uuid: field(4),
+            brand: field(5),
+        })
+    }
+}
+
+/// Somewhere we can discover zones and capture their configuration from.
+pub trait ZoneSource {
+    /// List all configured zones, excluding the global zone.
+    fn find_zones(&self) -> Result<Vec<ZoneInfo>, anyhow::Error>;
+
+    /// Capture the given representation of `zone`'s configuration.
+    fn get_zonecfg(&self, zone: &str, repr: Representation) -> Result<Vec<u8>, anyhow::Error>;
+
+    /// Apply a zonecfg command script to `zone`, as `zonecfg -z ZONE -f` would.
+    fn import_zonecfg(&self, zone: &str, script: &[u8]) -> Result<(), anyhow::Error>;
+}
+
+/// The live system, queried via `zoneadm(8)` and `zonecfg(8)`.
+#[derive(Debug, Default)]
+pub struct CommandSource;
+
+impl ZoneSource for CommandSource {
+    fn find_zones(&self) -> Result<Vec<ZoneInfo>, anyhow::Error> {
+        let zoneadm = Command::new(ZONEADM)
+            .env_clear()
+            .args(["list", "-n", "-c", "-p"])
+            .output()?;
+
+        if !zoneadm.status.success() {
+            bail!("exec {ZONEADM}: failed {:?}", zoneadm.status);
+        }
+
+        zoneadm
+            .stdout
+            .lines()
+            .map(|line| ZoneInfo::from_parsable(&line?))
+            .collect::<Result<Vec<_>, _>>()
+            .context("failed to parse zoneadm output")
+    }
+
+    fn get_zonecfg(&self, zone: &str, repr: Representation) -> Result<Vec<u8>, anyhow::Error> {
+        let zonecfg = Command::new(ZONECFG)
+            .env_clear()
+            .args(["-z", zone, repr.subcommand()])
+            .output()?;
+
+        if !zonecfg.status.success() {
+            let stderr = String::from_utf8_lossy(&zonecfg.stderr);
+            bail!("exec {ZONECFG}: failed {:?} -- {stderr}", zonecfg.status);
+        }
+
+        if zonecfg.stdout.is_empty() {
+            bail!("no zonecfg {repr} for {zone}?");
+        }
+
+        Ok(zonecfg.stdout)
+    }
+
+    fn import_zonecfg(&self, zone: &str, script: &[u8]) -> Result<(), anyhow::Error> {
+        let mut child = Command::new(ZONECFG)
+            .env_clear()
+            .args(["-z", zone, "-f", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // dropping stdin once written signals EOF to zonecfg
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(script)?;
+        }
+        let zonecfg = child.wait_with_output()?;
+
+        if !zonecfg.status.success() {
+            let stderr = String::from_utf8_lossy(&zonecfg.stderr);
+            bail!("exec {ZONECFG}: failed {:?} -- {stderr}", zonecfg.status);
+        }
+
+        Ok(())
+    }
+}
+
+/// A directory of previously captured configs, holding a `ZONE.zone` (info)
+/// and/or `ZONE.export` file per zone.
+///
+/// If the directory contains a `zoneadm.list` file, zones are listed from it
+/// as if it were `zoneadm list -p` output, otherwise they are derived from the
+/// config file names.
+///
+/// Useful for exercising the backup pipeline on systems without zones.
+#[derive(Debug)]
+pub struct DirectorySource {
+    dir: PathBuf,
+}
+
+impl DirectorySource {
+    pub fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl ZoneSource for DirectorySource {
+    fn find_zones(&self) -> Result<Vec<ZoneInfo>, anyhow::Error> {
+        let list = self.dir.join("zoneadm.list");
+        if list.exists() {
+            let mut zones = fs::read_to_string(&list)
+                .with_context(|| format!("{list:?}"))?
+                .lines()
+                .map(ZoneInfo::from_parsable)
+                .collect::<Result<Vec<_>, _>>()
+                .with_context(|| format!("{list:?}"))?;
+            zones.sort_by(|a, b| a.name.cmp(&b.name));
+            return Ok(zones);
+        }
+
+        // read_dir order is unspecified, keep archives reproducible
+        let mut zones = BTreeSet::new();
+        for ent in fs::read_dir(&self.dir).with_context(|| format!("reading {:?}", self.dir))? {
+            let name = ent?.file_name();
+            if let Some((zone, _)) = name.to_str().and_then(Representation::from_member_name) {
+                zones.insert(zone.to_string());
+            }
+        }
+
+        Ok(zones.into_iter().map(ZoneInfo::named).collect())
+    }
+
+    fn get_zonecfg(&self, zone: &str, repr: Representation) -> Result<Vec<u8>, anyhow::Error> {
+        let path = self.dir.join(repr.member_name(zone));
+        let data = fs::read(&path).with_context(|| format!("{path:?}"))?;
+
+        if data.is_empty() {
+            bail!("no zonecfg {repr} for {zone}?");
+        }
+
+        Ok(data)
+    }
+
+    fn import_zonecfg(&self, zone: &str, script: &[u8]) -> Result<(), anyhow::Error> {
+        let path = self.dir.join(Representation::Export.member_name(zone));
+        fs::write(&path, script).with_context(|| format!("{path:?}"))
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+
+use crate::{
+    archive, file_latest, file_sidecar, find_backups, generate_hash,
+    manifest::{Manifest, MANIFEST_NAME},
+    source::Representation,
+    Ctx,
+};
+
+/// What was checked for an archive that passed verification.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Verified {
+    /// Number of members decoded.
+    pub members: usize,
+    /// Whether a checksum sidecar was found and matched. Archives written
+    /// before sidecars existed have none.
+    pub checksum: bool,
+    /// Whether member hashes were checked against a manifest.
+    pub manifest: bool,
+}
+
+/// Check the archive against its sidecar checksum, decode every member and
+/// compare the zone members to the hashes recorded in the manifest.
+pub fn verify_archive(path: &Path) -> Result<Verified, anyhow::Error> {
+    let sidecar = file_sidecar(path);
+    let checksum = match fs::read_to_string(&sidecar) {
+        Ok(contents) => {
+            let expected = contents.split_whitespace().next().unwrap_or_default();
+            let f = File::open(path).with_context(|| format!("{path:?}"))?;
+            let actual = generate_hash(f).with_context(|| format!("hashing {path:?}"))?;
+            if expected != actual {
+                bail!("checksum {actual} does not match {expected} in {sidecar:?}");
+            }
+            true
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+        Err(e) => return Err(e).with_context(|| format!("{sidecar:?}")),
+    };
+
+    let mut manifest = None;
+    let mut hashes = BTreeMap::new();
+    let mut members = 0;
+    let mut archive = archive::open(path)?;
+    for entry in archive
+        .entries()
+        .with_context(|| format!("reading {path:?}"))?
+    {
+        let mut entry = entry.with_context(|| format!("reading {path:?}"))?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        members += 1;
+        if name == MANIFEST_NAME {
+            let mut data = Vec::new();
+            entry
+                .read_to_end(&mut data)
+                .with_context(|| format!("reading {name} from {path:?}"))?;
+            let parsed: Manifest = serde_json::from_slice(&data)
+                .with_context(|| format!("parsing {name} in {path:?}"))?;
+            manifest = Some(parsed);
+            continue;
+        }
+        let hash = generate_hash(entry).with_context(|| format!("reading {name} from {path:?}"))?;
+        if Representation::from_member_name(&name).is_some() {
+            hashes.insert(name, hash);
+        }
+    }
+
+    let manifest = match manifest {
+        Some(manifest) => manifest,
+        None => {
+            return Ok(Verified {
+                members,
+                checksum,
+                manifest: false,
+            })
+        }
+    };
+    let mut expected: BTreeMap<String, String> = BTreeMap::new();
+    for zone in manifest.zones {
+        expected.extend(zone.members);
+    }
+    for (name, hash) in &expected {
+        match hashes.get(name) {
+            None => bail!("{name} is in the manifest but missing from the archive"),
+            Some(actual) if actual != hash => bail!("{name} does not match the manifest"),
+            Some(_) => (),
+        }
+    }
+    if let Some(name) = hashes.keys().find(|name| !expected.contains_key(*name)) {
+        bail!("{name} is not in the manifest");
+    }
+
+    Ok(Verified {
+        members,
+        checksum,
+        manifest: true,
+    })
+}
+
+/// The backup `_latest` points at, failing if the link is missing, dangling
+/// or points at anything but one of the backups in `outdir`.
+pub fn verify_latest(c: &Ctx) -> Result<PathBuf, anyhow::Error> {
+    let latest = file_latest(c);
+    let target = fs::read_link(&latest).with_context(|| format!("reading link {latest:?}"))?;
+    let target = c.config.outdir.join(target);
+    if !target.is_file() {
+        bail!("{latest:?} points at missing backup {target:?}");
+    }
+
+    let canonical = fs::canonicalize(&target).with_context(|| format!("{target:?}"))?;
+    for backup in find_backups(c)? {
+        if fs::canonicalize(&backup.path).ok().as_ref() == Some(&canonical) {
+            return Ok(target);
+        }
+    }
+
+    bail!("{latest:?} points at {target:?}, which is not a backup in outdir")
+}
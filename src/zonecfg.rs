@@ -0,0 +1,297 @@
+use std::{collections::BTreeMap, fmt};
+
+use serde::{Deserialize, Serialize};
+
+/// A zone configuration parsed from `zonecfg -z ZONE info` output.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ZoneConfig {
+    /// Global properties such as `zonepath`, `brand` and `autoboot`. Properties
+    /// that are not set are left out.
+    pub properties: BTreeMap<String, String>,
+    /// Resources in the order zonecfg listed them.
+    pub resources: Vec<Resource>,
+}
+
+/// A resource such as `net` or `fs`, of which a zone may have several.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Resource {
+    pub kind: ResourceKind,
+    /// Properties that are set on this resource. zonecfg lists some, such as
+    /// an rctl's `value`, once per value.
+    pub properties: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Value {
+    Single(String),
+    List(Vec<String>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(from = "String", into = "String")]
+pub enum ResourceKind {
+    Net,
+    Fs,
+    Dataset,
+    Device,
+    CappedMemory,
+    CappedCpu,
+    DedicatedCpu,
+    Rctl,
+    Attr,
+    Admin,
+    SecurityFlags,
+    Other(String),
+}
+
+/// A line of `zonecfg info` output that could not be understood.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    /// 1-based line number.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl ResourceKind {
+    pub fn as_str(&self) -> &str {
+        match self {
+            ResourceKind::Net => "net",
+            ResourceKind::Fs => "fs",
+            ResourceKind::Dataset => "dataset",
+            ResourceKind::Device => "device",
+            ResourceKind::CappedMemory => "capped-memory",
+            ResourceKind::CappedCpu => "capped-cpu",
+            ResourceKind::DedicatedCpu => "dedicated-cpu",
+            ResourceKind::Rctl => "rctl",
+            ResourceKind::Attr => "attr",
+            ResourceKind::Admin => "admin",
+            ResourceKind::SecurityFlags => "security-flags",
+            ResourceKind::Other(kind) => kind,
+        }
+    }
+
+    /// The property that tells apart several resources of this kind, `None`
+    /// for resources a zone can only have one of.
+    pub fn identity(&self) -> Option<&'static str> {
+        match self {
+            ResourceKind::Net => Some("physical"),
+            ResourceKind::Fs => Some("dir"),
+            ResourceKind::Dataset | ResourceKind::Rctl | ResourceKind::Attr => Some("name"),
+            ResourceKind::Device => Some("match"),
+            ResourceKind::Admin => Some("user"),
+            _ => None,
+        }
+    }
+}
+
+impl From<String> for ResourceKind {
+    fn from(kind: String) -> Self {
+        match kind.as_str() {
+            "net" => ResourceKind::Net,
+            "fs" => ResourceKind::Fs,
+            "dataset" => ResourceKind::Dataset,
+            "device" => ResourceKind::Device,
+            "capped-memory" => ResourceKind::CappedMemory,
+            "capped-cpu" => ResourceKind::CappedCpu,
+            "dedicated-cpu" => ResourceKind::DedicatedCpu,
+            "rctl" => ResourceKind::Rctl,
+            "attr" => ResourceKind::Attr,
+            "admin" => ResourceKind::Admin,
+            "security-flags" => ResourceKind::SecurityFlags,
+            _ => ResourceKind::Other(kind),
+        }
+    }
+}
+
+impl From<ResourceKind> for String {
+    fn from(kind: ResourceKind) -> Self {
+        kind.as_str().to_string()
+    }
+}
+
+impl fmt::Display for ResourceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Value {
+    /// Parse a property value, splitting `[a,b]` lists into their elements.
+    fn parse(raw: &str) -> Self {
+        match raw.strip_prefix('[').and_then(|r| r.strip_suffix(']')) {
+            Some(list) => Value::List(
+                list.split(',')
+                    .filter(|v| !v.is_empty())
+                    .map(str::to_string)
+                    .collect(),
+            ),
+            None => Value::Single(raw.to_string()),
+        }
+    }
+
+    fn push(&mut self, other: Value) {
+        let mut values = match std::mem::replace(self, Value::List(Vec::new())) {
+            Value::Single(v) => vec![v],
+            Value::List(l) => l,
+        };
+        match other {
+            Value::Single(v) => values.push(v),
+            Value::List(l) => values.extend(l),
+        }
+        *self = Value::List(values);
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Single(v) => f.write_str(v),
+            Value::List(l) => write!(f, "[{}]", l.join(",")),
+        }
+    }
+}
+
+impl Resource {
+    pub fn get(&self, prop: &str) -> Option<&Value> {
+        self.properties.get(prop)
+    }
+
+    /// Identifies this resource among others of its kind, as in
+    /// `net[physical=vnic0]` or `capped-memory`.
+    pub fn key(&self) -> String {
+        match self
+            .kind
+            .identity()
+            .and_then(|p| self.get(p).map(|v| (p, v)))
+        {
+            Some((prop, value)) => format!("{}[{prop}={value}]", self.kind),
+            None => self.kind.to_string(),
+        }
+    }
+}
+
+/// Split a `prop: value` line, or one wrapped in brackets as zonecfg does for
+/// values derived from other settings, e.g. `[swap: 8G]`. Returns `None` for
+/// a value that is `not specified`.
+fn split_property(line: &str) -> Result<Option<(&str, &str)>, String> {
+    let line = match line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+        Some(inner) if inner.contains(": ") => inner,
+        _ => line,
+    };
+    if line.ends_with(" not specified") {
+        return Ok(None);
+    }
+    match line.split_once(':') {
+        Some((prop, value)) if !prop.is_empty() && !prop.contains(' ') => {
+            Ok(Some((prop, value.strip_prefix(' ').unwrap_or(value))))
+        }
+        _ => Err(format!("expected \"property: value\", found {line:?}")),
+    }
+}
+
+impl ZoneConfig {
+    /// Parse the output of `zonecfg -z ZONE info`.
+    pub fn parse(info: &[u8]) -> Result<Self, ParseError> {
+        let info = std::str::from_utf8(info).map_err(|e| {
+            let line = info[..e.valid_up_to()]
+                .iter()
+                .filter(|&&b| b == b'\n')
+                .count();
+            ParseError {
+                line: line + 1,
+                message: "invalid utf-8".to_string(),
+            }
+        })?;
+
+        let mut config = ZoneConfig::default();
+        let mut current: Option<Resource> = None;
+        for (i, line) in info.lines().enumerate() {
+            let error = |message: String| ParseError {
+                line: i + 1,
+                message,
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(prop) = line.strip_prefix('\t') {
+                let resource = current.as_mut().ok_or_else(|| {
+                    error(format!("resource property outside a resource: {prop:?}"))
+                })?;
+                if let Some((prop, value)) = split_property(prop).map_err(error)? {
+                    let value = Value::parse(value);
+                    match resource.properties.get_mut(prop) {
+                        Some(existing) => existing.push(value),
+                        None => {
+                            resource.properties.insert(prop.to_string(), value);
+                        }
+                    }
+                }
+                continue;
+            }
+
+            // an unindented "kind:" with nothing after it starts a resource
+            if let Some(kind) = line.strip_suffix(':') {
+                if kind.is_empty() || kind.contains(' ') {
+                    return Err(error(format!("invalid resource type {kind:?}")));
+                }
+                config.resources.extend(current.take());
+                current = Some(Resource {
+                    kind: ResourceKind::from(kind.to_string()),
+                    properties: BTreeMap::new(),
+                });
+                continue;
+            }
+
+            config.resources.extend(current.take());
+            if let Some((prop, value)) = split_property(line).map_err(error)? {
+                if !value.is_empty() {
+                    config
+                        .properties
+                        .insert(prop.to_string(), value.to_string());
+                }
+            }
+        }
+        config.resources.extend(current);
+
+        Ok(config)
+    }
+
+    pub fn get(&self, prop: &str) -> Option<&str> {
+        self.properties.get(prop).map(String::as_str)
+    }
+
+    pub fn zonename(&self) -> Option<&str> {
+        self.get("zonename")
+    }
+
+    pub fn zonepath(&self) -> Option<&str> {
+        self.get("zonepath")
+    }
+
+    pub fn brand(&self) -> Option<&str> {
+        self.get("brand")
+    }
+
+    pub fn autoboot(&self) -> Option<bool> {
+        self.get("autoboot").and_then(|v| v.parse().ok())
+    }
+
+    pub fn ip_type(&self) -> Option<&str> {
+        self.get("ip-type")
+    }
+
+    /// All resources of the given kind.
+    pub fn resources<'a>(&'a self, kind: &'a ResourceKind) -> impl Iterator<Item = &'a Resource> {
+        self.resources.iter().filter(move |r| &r.kind == kind)
+    }
+}
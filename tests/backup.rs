@@ -0,0 +1,417 @@
+use std::fs;
+
+use common::{archives, context, copy_fixtures, fixtures, read_archive};
+use zonecfg_backup::{
+    archive, capture_zone_configs, file_latest, file_snapshot, find_latest_snapshot,
+    manifest::{Manifest, MANIFEST_NAME},
+    plan_commit, prune_zonecfg_backups, replace_symlink,
+    report::ChangeReport,
+    resolve_snapshot,
+    semantic::Change,
+    snapshot_zone_configs,
+    source::{DirectorySource, Representation, ZoneInfo, ZoneSource},
+    summarize_snapshots, try_commit_zone_snapshot,
+    verify::verify_latest,
+    BackupName,
+};
+
+mod common;
+
+#[test]
+fn directory_source_lists_zones_sorted() {
+    let source = DirectorySource::new(fixtures());
+    let zones = source.find_zones().unwrap();
+    let names: Vec<_> = zones.iter().map(|z| z.name.as_str()).collect();
+    assert_eq!(names, ["db01", "dns", "web01"]);
+    assert_eq!(zones[2].state.as_deref(), Some("installed"));
+    assert_eq!(zones[2].brand.as_deref(), Some("bhyve"));
+    assert!(source
+        .get_zonecfg("nosuchzone", Representation::Info)
+        .is_err());
+}
+
+#[test]
+fn snapshot_contains_every_zone() {
+    let (_outdir, ctx) = context("");
+    let snapshot = snapshot_zone_configs(&ctx, &DirectorySource::new(fixtures())).unwrap();
+
+    let members = read_archive(snapshot.file.path());
+    assert_eq!(
+        members.keys().collect::<Vec<_>>(),
+        [
+            MANIFEST_NAME,
+            "db01.export",
+            "db01.zone",
+            "dns.export",
+            "dns.zone",
+            "web01.export",
+            "web01.zone"
+        ]
+    );
+    for (name, contents) in members {
+        if name != MANIFEST_NAME {
+            assert_eq!(contents, fs::read(fixtures().join(name)).unwrap());
+        }
+    }
+}
+
+#[test]
+fn zoneadm_parsable_output() {
+    let zone = ZoneInfo::from_parsable(
+        "-:odd:configured:/zones/we\\:ird:4f9e2c1a-0b7d-4e36-9a85-c2d1f0e3b4a6:sparse:excl:-:none:",
+    )
+    .unwrap();
+    assert_eq!(zone.name, "odd");
+    assert_eq!(zone.state.as_deref(), Some("configured"));
+    assert_eq!(
+        zone.uuid.as_deref(),
+        Some("4f9e2c1a-0b7d-4e36-9a85-c2d1f0e3b4a6")
+    );
+    assert_eq!(zone.brand.as_deref(), Some("sparse"));
+    assert!(ZoneInfo::from_parsable("db01").is_err());
+}
+
+#[test]
+fn snapshot_leads_with_manifest() {
+    let (_outdir, ctx) = context("");
+    let zones = copy_fixtures();
+    let mut list = fs::read_to_string(zones.path().join("zoneadm.list")).unwrap();
+    list.push_str("-:gone:configured:/zones/gone::sparse:excl\n");
+    fs::write(zones.path().join("zoneadm.list"), list).unwrap();
+    let snapshot = snapshot_zone_configs(&ctx, &DirectorySource::new(zones.path())).unwrap();
+
+    let mut archive = archive::open(snapshot.file.path()).unwrap();
+    let first = archive.entries().unwrap().next().unwrap().unwrap();
+    assert_eq!(first.path().unwrap().to_str(), Some(MANIFEST_NAME));
+
+    let manifest = Manifest::from_archive(snapshot.file.path())
+        .unwrap()
+        .unwrap();
+    assert_eq!(manifest.prefix, "test");
+    assert_eq!(manifest.tool_version, env!("CARGO_PKG_VERSION"));
+    let db01 = &manifest.zones[0];
+    assert_eq!(db01.info.name, "db01");
+    assert_eq!(
+        db01.info.uuid.as_deref(),
+        Some("0d5b4f7c-8b1e-4c2a-9d8e-2f6a1c3b7e90")
+    );
+    let members = read_archive(snapshot.file.path());
+    for zone in &manifest.zones {
+        for (member, hash) in &zone.members {
+            let expected = zonecfg_backup::generate_hash(members[member].as_slice()).unwrap();
+            assert_eq!(hash, &expected);
+        }
+    }
+    assert_eq!(manifest.failed.len(), 1);
+    assert_eq!(manifest.failed[0].name, "gone");
+}
+
+#[test]
+fn snapshot_captures_configured_representations() {
+    let (_outdir, ctx) = context("capture = [\"export\"]");
+    let snapshot = snapshot_zone_configs(&ctx, &DirectorySource::new(fixtures())).unwrap();
+
+    let members = read_archive(snapshot.file.path());
+    assert_eq!(
+        members.keys().collect::<Vec<_>>(),
+        [MANIFEST_NAME, "db01.export", "dns.export", "web01.export"]
+    );
+}
+
+#[test]
+fn snapshot_applies_zone_filters() {
+    let (_outdir, ctx) = context("include = [\"d*\", \"web*\"]\nexclude = [\"db0[0-9]\"]");
+    let snapshot = snapshot_zone_configs(&ctx, &DirectorySource::new(fixtures())).unwrap();
+
+    let members = read_archive(snapshot.file.path());
+    assert_eq!(
+        members.keys().collect::<Vec<_>>(),
+        [
+            MANIFEST_NAME,
+            "dns.export",
+            "dns.zone",
+            "web01.export",
+            "web01.zone"
+        ]
+    );
+}
+
+#[test]
+fn commit_writes_archive_and_latest() {
+    let (outdir, ctx) = context("");
+    let snapshot = snapshot_zone_configs(&ctx, &DirectorySource::new(fixtures())).unwrap();
+    try_commit_zone_snapshot(&ctx, snapshot).unwrap();
+
+    let written = archives(outdir.path());
+    assert_eq!(written.len(), 1);
+    let latest = find_latest_snapshot(&ctx).unwrap().unwrap();
+    assert_eq!(latest, outdir.path().join(&written[0]));
+    assert_eq!(read_archive(file_latest(&ctx)).len(), 7);
+}
+
+#[test]
+fn latest_is_swapped_in_place() {
+    let (outdir, ctx) = context("");
+    let latest = file_latest(&ctx);
+    replace_symlink(&outdir.path().join("a"), &latest).unwrap();
+    replace_symlink(&outdir.path().join("b"), &latest).unwrap();
+
+    assert_eq!(fs::read_link(&latest).unwrap(), outdir.path().join("b"));
+    let names: Vec<_> = fs::read_dir(outdir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect();
+    assert_eq!(names, ["test_latest"]);
+}
+
+#[test]
+fn relative_latest_resolves_against_outdir() {
+    let (outdir, ctx) = context("");
+    let snapshot = file_snapshot(&ctx, 1662780550);
+    fs::write(&snapshot, b"").unwrap();
+    std::os::unix::fs::symlink("test_1662780550.zones.tar.zst", file_latest(&ctx)).unwrap();
+
+    assert_eq!(find_latest_snapshot(&ctx).unwrap(), Some(snapshot.clone()));
+    assert_eq!(resolve_snapshot(&ctx, None).unwrap(), snapshot);
+    assert_eq!(verify_latest(&ctx).unwrap(), snapshot);
+    assert!(snapshot.starts_with(outdir.path()));
+}
+
+#[test]
+fn unchanged_snapshot_is_not_committed() {
+    let (outdir, ctx) = context("");
+    let source = DirectorySource::new(fixtures());
+    try_commit_zone_snapshot(&ctx, snapshot_zone_configs(&ctx, &source).unwrap()).unwrap();
+
+    // backdate the first snapshot so a second write can't reuse its name
+    let first = outdir.path().join("test_1662780557.zones.tar.zst");
+    fs::rename(find_latest_snapshot(&ctx).unwrap().unwrap(), &first).unwrap();
+    fs::remove_file(file_latest(&ctx)).unwrap();
+    std::os::unix::fs::symlink(&first, file_latest(&ctx)).unwrap();
+    try_commit_zone_snapshot(&ctx, snapshot_zone_configs(&ctx, &source).unwrap()).unwrap();
+
+    assert_eq!(archives(outdir.path()), ["test_1662780557.zones.tar.zst"]);
+    assert_eq!(find_latest_snapshot(&ctx).unwrap(), Some(first));
+}
+
+#[test]
+fn compression_level_change_is_not_a_change() {
+    let (outdir, mut ctx) = context("compression_level = 3");
+    let source = DirectorySource::new(fixtures());
+    let first = outdir.path().join("test_1662780557.zones.tar.zst");
+    let snapshot = snapshot_zone_configs(&ctx, &source).unwrap();
+    snapshot.file.persist(&first).unwrap();
+    std::os::unix::fs::symlink(&first, file_latest(&ctx)).unwrap();
+
+    ctx.config.settings.compression_level = Some(19);
+    try_commit_zone_snapshot(&ctx, snapshot_zone_configs(&ctx, &source).unwrap()).unwrap();
+
+    assert_eq!(archives(outdir.path()), ["test_1662780557.zones.tar.zst"]);
+}
+
+#[test]
+fn planned_commit_writes_nothing() {
+    let (outdir, ctx) = context("");
+    let scratch = tempfile::tempdir().unwrap();
+    let snapshot = capture_zone_configs(&ctx, &DirectorySource::new(fixtures()))
+        .unwrap()
+        .write_snapshot_in(&ctx, scratch.path())
+        .unwrap();
+    let commit = plan_commit(&ctx, &snapshot).unwrap();
+
+    assert!(commit.archive.unwrap().starts_with(outdir.path()));
+    assert_eq!(commit.changes.added, ["db01", "dns", "web01"]);
+    assert_eq!(fs::read_dir(outdir.path()).unwrap().count(), 0);
+    assert_eq!(find_latest_snapshot(&ctx).unwrap(), None);
+}
+
+#[test]
+fn changed_snapshot_is_committed() {
+    let (_outdir, ctx) = context("");
+    let zones = copy_fixtures();
+    let source = DirectorySource::new(zones.path());
+    try_commit_zone_snapshot(&ctx, snapshot_zone_configs(&ctx, &source).unwrap()).unwrap();
+
+    let dns = zones.path().join("dns.zone");
+    let edited = fs::read_to_string(&dns)
+        .unwrap()
+        .replace("autoboot: true", "autoboot: false");
+    fs::write(&dns, &edited).unwrap();
+    try_commit_zone_snapshot(&ctx, snapshot_zone_configs(&ctx, &source).unwrap()).unwrap();
+
+    let members = read_archive(file_latest(&ctx));
+    assert_eq!(members["dns.zone"], edited.as_bytes());
+}
+
+#[test]
+fn commit_reports_zone_changes() {
+    let (_outdir, ctx) = context("");
+    let zones = copy_fixtures();
+    fs::remove_file(zones.path().join("zoneadm.list")).unwrap();
+    let source = DirectorySource::new(zones.path());
+    let commit =
+        try_commit_zone_snapshot(&ctx, snapshot_zone_configs(&ctx, &source).unwrap()).unwrap();
+    assert!(commit.archive.is_some());
+    assert_eq!(commit.changes.added, ["db01", "dns", "web01"]);
+
+    let dns = zones.path().join("dns.zone");
+    let edited =
+        fs::read_to_string(&dns).unwrap() + "attr:\n\tname: owner\n\ttype: string\n\tvalue: ops\n";
+    fs::write(&dns, edited).unwrap();
+    fs::remove_file(zones.path().join("web01.zone")).unwrap();
+    fs::remove_file(zones.path().join("web01.export")).unwrap();
+    fs::copy(fixtures().join("db01.zone"), zones.path().join("db02.zone")).unwrap();
+    fs::copy(
+        fixtures().join("db01.export"),
+        zones.path().join("db02.export"),
+    )
+    .unwrap();
+    let commit =
+        try_commit_zone_snapshot(&ctx, snapshot_zone_configs(&ctx, &source).unwrap()).unwrap();
+
+    assert_eq!(
+        commit.changes,
+        ChangeReport {
+            added: vec!["db02".to_string()],
+            removed: vec!["web01".to_string()],
+            modified: vec!["dns".to_string()],
+            details: [(
+                "dns".to_string(),
+                vec![Change::Added {
+                    path: "attr[name=owner]".to_string(),
+                    value: None,
+                }]
+            )]
+            .into(),
+        }
+    );
+}
+
+#[test]
+fn prune_keeps_newest_backups() {
+    let (outdir, ctx) = context("");
+    for ts in 1662780550..1662780557 {
+        fs::write(outdir.path().join(format!("test_{ts}.zones.tar.zst")), b"").unwrap();
+    }
+    let newest = outdir.path().join("test_1662780556.zones.tar.zst");
+    std::os::unix::fs::symlink(&newest, file_latest(&ctx)).unwrap();
+
+    prune_zonecfg_backups(&ctx).unwrap();
+
+    assert_eq!(
+        archives(outdir.path()),
+        [
+            "test_1662780554.zones.tar.zst",
+            "test_1662780555.zones.tar.zst",
+            "test_1662780556.zones.tar.zst",
+        ]
+    );
+    assert_eq!(find_latest_snapshot(&ctx).unwrap(), Some(newest));
+}
+
+#[test]
+fn backup_names_parse_strictly() {
+    assert_eq!(
+        BackupName::parse("test", "test_1662780550.zones.tar.zst"),
+        Some(BackupName {
+            prefix: "test".to_string(),
+            timestamp: 1662780550,
+        })
+    );
+    for name in [
+        "test_latest",
+        "test.conf",
+        "test_1662780550.zones.tar.zst.sha256",
+        "test_01662780550.zones.tar.zst",
+        "test_+1662780550.zones.tar.zst",
+        "test_-1.zones.tar.zst",
+        "test_.zones.tar.zst",
+        "test2_1662780550.zones.tar.zst",
+        "test_x_1662780550.zones.tar.zst",
+    ] {
+        assert_eq!(BackupName::parse("test", name), None, "{name}");
+    }
+}
+
+#[test]
+fn prune_orders_by_timestamp_and_ignores_other_files() {
+    let (outdir, ctx) = context("");
+    for name in [
+        "test_999999999.zones.tar.zst",
+        "test_1662780550.zones.tar.zst",
+        "test_1662780551.zones.tar.zst",
+        "test_1662780552.zones.tar.zst",
+        "test.conf",
+        "test_01662780540.zones.tar.zst",
+        "test_notes.txt",
+    ] {
+        fs::write(outdir.path().join(name), b"").unwrap();
+    }
+    fs::create_dir(outdir.path().join("test_1662780599.zones.tar.zst")).unwrap();
+
+    prune_zonecfg_backups(&ctx).unwrap();
+
+    let mut left: Vec<String> = fs::read_dir(outdir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    left.sort();
+    assert_eq!(
+        left,
+        [
+            "test.conf",
+            "test_01662780540.zones.tar.zst",
+            "test_1662780550.zones.tar.zst",
+            "test_1662780551.zones.tar.zst",
+            "test_1662780552.zones.tar.zst",
+            "test_1662780599.zones.tar.zst",
+            "test_notes.txt",
+        ]
+    );
+}
+
+#[test]
+fn summaries_describe_each_backup() {
+    let (outdir, ctx) = context("");
+    let snapshot = snapshot_zone_configs(&ctx, &DirectorySource::new(fixtures())).unwrap();
+    let committed = try_commit_zone_snapshot(&ctx, snapshot)
+        .unwrap()
+        .archive
+        .unwrap();
+    fs::write(file_snapshot(&ctx, 1662780550), b"not an archive").unwrap();
+
+    let summaries = summarize_snapshots(&ctx).unwrap();
+    assert_eq!(summaries.len(), 2);
+
+    let newest = &summaries[0];
+    assert_eq!(newest.path, committed);
+    assert!(newest.latest);
+    assert_eq!(newest.size, fs::metadata(&committed).unwrap().len());
+    assert_eq!(
+        newest.zones.as_deref(),
+        Some(&["db01".to_string(), "dns".to_string(), "web01".to_string()][..])
+    );
+
+    let broken = &summaries[1];
+    assert_eq!(
+        broken.path,
+        outdir.path().join("test_1662780550.zones.tar.zst")
+    );
+    assert_eq!(broken.timestamp.unwrap().timestamp(), 1662780550);
+    assert!(!broken.latest);
+    assert_eq!(broken.zones, None);
+}
+
+#[test]
+fn copy_member_streams_a_single_zone() {
+    let (_outdir, ctx) = context("");
+    let snapshot = snapshot_zone_configs(&ctx, &DirectorySource::new(fixtures())).unwrap();
+
+    let mut out = Vec::new();
+    assert!(archive::copy_member(snapshot.file.path(), "dns.export", &mut out).unwrap());
+    assert_eq!(out, fs::read(fixtures().join("dns.export")).unwrap());
+
+    let mut out = Vec::new();
+    assert!(!archive::copy_member(snapshot.file.path(), "nope.zone", &mut out).unwrap());
+    assert!(out.is_empty());
+}
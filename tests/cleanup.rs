@@ -0,0 +1,74 @@
+use std::{
+    fs,
+    process::{Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use common::{context, fixtures};
+use zonecfg_backup::{
+    cleanup::{remove_stale_tempfiles, TEMP_PREFIX},
+    snapshot_zone_configs,
+    source::DirectorySource,
+};
+
+mod common;
+
+#[test]
+fn tempfiles_are_recognizable() {
+    let (_outdir, ctx) = context("");
+    let snapshot = snapshot_zone_configs(&ctx, &DirectorySource::new(fixtures())).unwrap();
+    let name = snapshot.file.path().file_name().unwrap().to_str().unwrap();
+    assert!(name.starts_with(TEMP_PREFIX), "{name}");
+}
+
+#[test]
+fn only_stale_tempfiles_are_removed() {
+    let (outdir, ctx) = context("");
+    let stale = outdir.path().join(format!("{TEMP_PREFIX}AbC123"));
+    let other = outdir.path().join(".tmpXyZ789");
+    fs::write(&stale, b"half written").unwrap();
+    fs::write(&other, b"not ours").unwrap();
+
+    remove_stale_tempfiles(&ctx, Duration::from_secs(60 * 60)).unwrap();
+    assert!(stale.exists());
+
+    remove_stale_tempfiles(&ctx, Duration::ZERO).unwrap();
+    assert!(!stale.exists());
+    assert!(other.exists());
+}
+
+#[test]
+fn signals_exit_with_their_number() {
+    for (signal, status) in [(libc::SIGINT, 130), (libc::SIGTERM, 143)] {
+        let outdir = tempfile::tempdir().unwrap();
+        let started = outdir.path().join("started");
+        let config = outdir.path().join("config.toml");
+        fs::write(
+            &config,
+            format!(
+                "outdir = {:?}\n[hooks]\npre_backup = \"touch {}; sleep 5\"\n",
+                outdir.path(),
+                started.display()
+            ),
+        )
+        .unwrap();
+
+        let mut child = Command::new(env!("CARGO_BIN_EXE_zonecfg-backup"))
+            .arg("-c")
+            .arg(&config)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        while !started.exists() {
+            assert!(child.try_wait().unwrap().is_none(), "exited early");
+            thread::sleep(Duration::from_millis(10));
+        }
+        // SAFETY: plain kill(2) of our own child
+        unsafe { libc::kill(child.id() as libc::pid_t, signal) };
+
+        assert_eq!(child.wait().unwrap().code(), Some(status));
+    }
+}
@@ -0,0 +1,74 @@
+#![allow(dead_code)]
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use slog::{o, Logger};
+use tempfile::TempDir;
+use zonecfg_backup::{config::Config, Ctx};
+
+pub fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/zones")
+}
+
+/// Build a context writing into a fresh outdir, with `extra` appended to the
+/// generated config file.
+pub fn context(extra: &str) -> (TempDir, Ctx) {
+    let outdir = tempfile::tempdir().unwrap();
+    let config_path = outdir.path().join("config.toml");
+    fs::write(
+        &config_path,
+        format!(
+            "outdir = {:?}\nnumber_of_backups = 3\nprefix = \"test\"\n{extra}",
+            outdir.path()
+        ),
+    )
+    .unwrap();
+    let ctx = Ctx {
+        config: Config::from_file(&config_path).unwrap(),
+        log: Logger::root(slog::Discard, o!()),
+    };
+    fs::remove_file(config_path).unwrap();
+
+    (outdir, ctx)
+}
+
+/// Decode a snapshot archive into a map of member name to contents.
+pub fn read_archive<P: AsRef<Path>>(path: P) -> BTreeMap<String, Vec<u8>> {
+    let decoder = zstd::Decoder::new(File::open(path).unwrap()).unwrap();
+    let mut archive = tar::Archive::new(decoder);
+    let mut members = BTreeMap::new();
+    for entry in archive.entries().unwrap() {
+        let mut entry = entry.unwrap();
+        let name = entry.path().unwrap().to_string_lossy().into_owned();
+        let mut buf = Vec::new();
+        entry.read_to_end(&mut buf).unwrap();
+        members.insert(name, buf);
+    }
+
+    members
+}
+
+pub fn archives(outdir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(outdir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .filter(|n| n.ends_with(".zones.tar.zst"))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Copy the fixture zones somewhere they can be modified.
+pub fn copy_fixtures() -> TempDir {
+    let zones = tempfile::tempdir().unwrap();
+    for ent in fs::read_dir(fixtures()).unwrap() {
+        let ent = ent.unwrap();
+        fs::copy(ent.path(), zones.path().join(ent.file_name())).unwrap();
+    }
+    zones
+}
@@ -0,0 +1,137 @@
+use std::fs;
+
+use common::{archives, copy_fixtures};
+use slog::{o, Logger};
+use zonecfg_backup::{
+    capture_zone_configs, config::Config, file_latest, file_snapshot, find_latest_snapshot,
+    prune_zonecfg_backups, source::DirectorySource, try_commit_zone_snapshot, Ctx,
+};
+
+mod common;
+
+fn parse(toml: &str) -> Config {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+    fs::write(&path, toml).unwrap();
+    Config::from_file(path).unwrap()
+}
+
+#[test]
+fn destinations_fall_back_to_top_level_settings() {
+    let config = parse(
+        "prefix = \"test\"\nkeep_last = 3\n\
+         [[destination]]\nname = \"fast\"\noutdir = \"/a\"\nkeep_last = 1\n\
+         [[destination]]\noutdir = \"/b\"\nprefix = \"offsite\"\n",
+    );
+    let destinations = config.destinations().unwrap();
+    let summary: Vec<_> = destinations
+        .iter()
+        .map(|(name, c)| {
+            (
+                name.as_str(),
+                c.settings.prefix.as_deref(),
+                c.settings.keep_last,
+            )
+        })
+        .collect();
+    assert_eq!(
+        summary,
+        [
+            ("fast", Some("test"), Some(1)),
+            ("/b", Some("offsite"), Some(3))
+        ]
+    );
+
+    // an outdir can be shared under different prefixes
+    let shared = parse(
+        "[[destination]]\nname = \"a\"\noutdir = \"/a\"\n\
+         [[destination]]\nname = \"b\"\noutdir = \"/a\"\nprefix = \"b\"\n",
+    );
+    assert_eq!(shared.destinations().unwrap().len(), 2);
+
+    let single = parse("outdir = \"/a\"\n").destinations().unwrap();
+    assert_eq!(single.len(), 1);
+    assert_eq!(single[0].0, "/a");
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+    fs::write(&path, "[[destination]]\noutdir = \"/a\"\nkeep_lsat = 1\n").unwrap();
+    let err = Config::from_file(path).unwrap_err();
+    assert!(format!("{err:#}").contains("keep_lsat"), "{err:#}");
+
+    for (toml, error) in [
+        ("prefix = \"test\"\n", "either outdir or a [[destination]]"),
+        (
+            "outdir = \"/a\"\n[[destination]]\noutdir = \"/b\"\n",
+            "can't be combined",
+        ),
+        (
+            "[[destination]]\noutdir = \"/a\"\n[[destination]]\noutdir = \"/a\"\n",
+            "more than once",
+        ),
+        (
+            "[[destination]]\nname = \"a\"\noutdir = \"/a\"\n\
+             [[destination]]\nname = \"b\"\noutdir = \"/a\"\n",
+            "both write \"zonecfg-backup\" backups to \"/a\"",
+        ),
+    ] {
+        let err = parse(toml).destinations().unwrap_err();
+        assert!(err.to_string().contains(error), "{err}");
+    }
+}
+
+#[test]
+fn capture_is_committed_against_each_destinations_latest() {
+    let fast = tempfile::tempdir().unwrap();
+    let offsite = tempfile::tempdir().unwrap();
+    let config = parse(&format!(
+        "prefix = \"test\"\nkeep_last = 1\n\
+         [[destination]]\noutdir = {:?}\n\
+         [[destination]]\noutdir = {:?}\nprefix = \"offsite\"\nkeep_last = 3\n",
+        fast.path(),
+        offsite.path()
+    ));
+    let ctxs: Vec<Ctx> = config
+        .destinations()
+        .unwrap()
+        .into_iter()
+        .map(|(_, config)| Ctx {
+            config,
+            log: Logger::root(slog::Discard, o!()),
+        })
+        .collect();
+
+    let zones = copy_fixtures();
+    fs::remove_file(zones.path().join("zoneadm.list")).unwrap();
+    let db01 = zones.path().join("db01.zone");
+    let db01_config = fs::read(&db01).unwrap();
+    fs::remove_file(&db01).unwrap();
+    let source = DirectorySource::new(zones.path());
+
+    // only the first destination has seen the zones without db01
+    let capture = capture_zone_configs(&ctxs[0], &source).unwrap();
+    try_commit_zone_snapshot(&ctxs[0], capture.write_snapshot(&ctxs[0]).unwrap()).unwrap();
+    // backdate it so the next commit can't reuse its name
+    let first = file_snapshot(&ctxs[0], 1662780550);
+    fs::rename(find_latest_snapshot(&ctxs[0]).unwrap().unwrap(), &first).unwrap();
+    fs::remove_file(file_latest(&ctxs[0])).unwrap();
+    std::os::unix::fs::symlink(&first, file_latest(&ctxs[0])).unwrap();
+
+    fs::write(&db01, db01_config).unwrap();
+    let capture = capture_zone_configs(&ctxs[0], &source).unwrap();
+    let commits: Vec<_> = ctxs
+        .iter()
+        .map(|c| try_commit_zone_snapshot(c, capture.write_snapshot(c).unwrap()).unwrap())
+        .collect();
+    assert_eq!(commits[0].changes.added, ["db01"]);
+    assert_eq!(commits[1].changes.added, ["db01", "dns", "web01"]);
+
+    let written = archives(offsite.path());
+    assert_eq!(written.len(), 1);
+    assert!(written[0].starts_with("offsite_"), "{written:?}");
+
+    // retention is per destination
+    assert_eq!(archives(fast.path()).len(), 2);
+    assert_eq!(prune_zonecfg_backups(&ctxs[0]).unwrap(), [first]);
+    assert!(prune_zonecfg_backups(&ctxs[1]).unwrap().is_empty());
+}
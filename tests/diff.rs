@@ -0,0 +1,91 @@
+use std::fs;
+
+use common::{context, copy_fixtures, fixtures};
+use serde_json::json;
+use zonecfg_backup::{
+    diff::{diff_snapshots, semantic_diff},
+    file_latest, file_snapshot, find_previous_snapshot, snapshot_zone_configs,
+    source::DirectorySource,
+};
+
+mod common;
+
+#[test]
+fn diff_shows_changed_members_and_one_sided_zones() {
+    let (_outdir, ctx) = context("");
+    let old = file_snapshot(&ctx, 1662780557);
+    snapshot_zone_configs(&ctx, &DirectorySource::new(fixtures()))
+        .unwrap()
+        .file
+        .persist(&old)
+        .unwrap();
+
+    let zones = copy_fixtures();
+    fs::remove_file(zones.path().join("zoneadm.list")).unwrap();
+    let dns = zones.path().join("dns.zone");
+    let edited = fs::read_to_string(&dns)
+        .unwrap()
+        .replace("autoboot: true", "autoboot: false");
+    fs::write(&dns, edited).unwrap();
+    fs::remove_file(zones.path().join("web01.zone")).unwrap();
+    fs::remove_file(zones.path().join("web01.export")).unwrap();
+    for ext in ["zone", "export"] {
+        fs::copy(
+            fixtures().join(format!("dns.{ext}")),
+            zones.path().join(format!("ns2.{ext}")),
+        )
+        .unwrap();
+    }
+    let new = file_snapshot(&ctx, 1662780600);
+    snapshot_zone_configs(&ctx, &DirectorySource::new(zones.path()))
+        .unwrap()
+        .file
+        .persist(&new)
+        .unwrap();
+
+    let mut out = Vec::new();
+    assert!(diff_snapshots(&old, &new, false, &mut out).unwrap());
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.contains(
+        "--- test_1662780557.zones.tar.zst:dns.zone\n+++ test_1662780600.zones.tar.zst:dns.zone\n"
+    ));
+    assert!(out.contains("\n-autoboot: true\n+autoboot: false\n"));
+    assert!(!out.contains("db01"));
+    assert!(out.contains("Only in test_1662780557.zones.tar.zst: web01\n"));
+    assert!(out.contains("Only in test_1662780600.zones.tar.zst: ns2\n"));
+
+    let mut out = Vec::new();
+    assert!(diff_snapshots(&old, &new, true, &mut out).unwrap());
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "dns: autoboot changed true → false\n\
+         Only in test_1662780557.zones.tar.zst: web01\n\
+         Only in test_1662780600.zones.tar.zst: ns2\n"
+    );
+    let diff = serde_json::to_value(semantic_diff(&old, &new).unwrap()).unwrap();
+    assert_eq!(
+        diff["zones"]["dns"],
+        json!([{"change": "changed", "path": "autoboot", "from": "true", "to": "false"}])
+    );
+    assert_eq!(diff["only_in_old"], json!(["web01"]));
+    assert_eq!(diff["only_in_new"], json!(["ns2"]));
+
+    let mut out = Vec::new();
+    assert!(!diff_snapshots(&new, &new, false, &mut out).unwrap());
+    assert!(out.is_empty());
+}
+
+#[test]
+fn previous_snapshot_precedes_latest() {
+    let (outdir, ctx) = context("");
+    for ts in [1662780550, 1662780551, 1662780552] {
+        fs::write(file_snapshot(&ctx, ts), b"").unwrap();
+    }
+    assert_eq!(find_previous_snapshot(&ctx).unwrap(), None);
+
+    std::os::unix::fs::symlink(file_snapshot(&ctx, 1662780551), file_latest(&ctx)).unwrap();
+    assert_eq!(
+        find_previous_snapshot(&ctx).unwrap(),
+        Some(outdir.path().join("test_1662780550.zones.tar.zst"))
+    );
+}
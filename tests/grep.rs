@@ -0,0 +1,60 @@
+use std::fs;
+
+use common::{context, copy_fixtures};
+use regex::Regex;
+use zonecfg_backup::{
+    file_latest, file_snapshot,
+    grep::{grep_snapshots, GrepOptions},
+    snapshot_zone_configs,
+    source::DirectorySource,
+};
+
+mod common;
+
+#[test]
+fn grep_searches_every_snapshot_unless_limited() {
+    let (_outdir, ctx) = context("");
+    let zones = copy_fixtures();
+    let source = DirectorySource::new(zones.path());
+    let first = file_snapshot(&ctx, 1662780550);
+    let snapshot = snapshot_zone_configs(&ctx, &source).unwrap();
+    snapshot.file.persist(&first).unwrap();
+
+    let dns = zones.path().join("dns.zone");
+    let edited = fs::read_to_string(&dns)
+        .unwrap()
+        .replace("10.0.0.53/24", "10.0.0.54/24");
+    fs::write(&dns, edited).unwrap();
+    let second = file_snapshot(&ctx, 1662780551);
+    let snapshot = snapshot_zone_configs(&ctx, &source).unwrap();
+    snapshot.file.persist(&second).unwrap();
+    std::os::unix::fs::symlink(&second, file_latest(&ctx)).unwrap();
+
+    let pattern = Regex::new(r"allowed-address: 10\.0\.0\.53/").unwrap();
+    let matches = grep_snapshots(&ctx, &pattern, &GrepOptions::default()).unwrap();
+    assert_eq!(matches.len(), 1);
+    assert_eq!(matches[0].archive, first);
+    assert_eq!(matches[0].zone, "dns");
+    assert_eq!(matches[0].member, "dns.zone");
+    assert_eq!(matches[0].line_number, 15);
+    assert_eq!(matches[0].line, "\tallowed-address: 10.0.0.53/24");
+
+    let latest = GrepOptions {
+        latest: true,
+        ..Default::default()
+    };
+    assert!(grep_snapshots(&ctx, &pattern, &latest).unwrap().is_empty());
+
+    let pattern = Regex::new("allowed-address:").unwrap();
+    let matches = grep_snapshots(&ctx, &pattern, &latest).unwrap();
+    assert_eq!(matches.len(), 3);
+    assert!(matches.iter().all(|m| m.archive == second));
+
+    let web01 = GrepOptions {
+        zones: vec!["web01".to_string()],
+        ..Default::default()
+    };
+    let matches = grep_snapshots(&ctx, &pattern, &web01).unwrap();
+    assert_eq!(matches.len(), 2);
+    assert!(matches.iter().all(|m| m.zone == "web01"));
+}
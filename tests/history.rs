@@ -0,0 +1,69 @@
+use std::{fs, path::Path};
+
+use common::{context, copy_fixtures};
+use zonecfg_backup::{
+    file_snapshot,
+    history::{zone_history, Event},
+    semantic::Change,
+    snapshot_zone_configs,
+    source::DirectorySource,
+    Ctx,
+};
+
+mod common;
+
+fn persist(ctx: &Ctx, zones: &Path, timestamp: i64) {
+    snapshot_zone_configs(ctx, &DirectorySource::new(zones))
+        .unwrap()
+        .file
+        .persist(file_snapshot(ctx, timestamp))
+        .unwrap();
+}
+
+#[test]
+fn history_follows_a_zone_across_snapshots() {
+    let (_outdir, ctx) = context("");
+    let zones = copy_fixtures();
+    fs::remove_file(zones.path().join("zoneadm.list")).unwrap();
+    let db01 = zones.path().join("db01.zone");
+    let config = fs::read_to_string(&db01).unwrap();
+    fs::remove_file(&db01).unwrap();
+    persist(&ctx, zones.path(), 1662780550);
+
+    fs::write(&db01, &config).unwrap();
+    persist(&ctx, zones.path(), 1662780551);
+    persist(&ctx, zones.path(), 1662780552);
+
+    let without_memory = config.replace("capped-memory:\n\tphysical: 8G\n\t[swap: 8G]\n", "");
+    fs::write(&db01, without_memory).unwrap();
+    persist(&ctx, zones.path(), 1662780553);
+
+    fs::remove_file(&db01).unwrap();
+    fs::remove_file(zones.path().join("db01.export")).unwrap();
+    persist(&ctx, zones.path(), 1662780554);
+
+    let history = zone_history(&ctx, "db01").unwrap();
+    let events: Vec<(i64, &Event)> = history
+        .iter()
+        .map(|e| (e.timestamp.unwrap().timestamp(), &e.event))
+        .collect();
+    assert_eq!(
+        events,
+        [
+            (1662780551, &Event::Appeared),
+            (
+                1662780553,
+                &Event::Changed {
+                    changes: vec![Change::Removed {
+                        path: "capped-memory".to_string(),
+                        value: None,
+                    }],
+                }
+            ),
+            (1662780554, &Event::Disappeared),
+        ]
+    );
+    assert_eq!(history[0].archive, file_snapshot(&ctx, 1662780551));
+
+    assert!(zone_history(&ctx, "nope").unwrap().is_empty());
+}
@@ -0,0 +1,73 @@
+use std::{fs, thread, time::Duration};
+
+use common::context;
+use zonecfg_backup::{
+    hooks::{change_vars, run_hook, Hook},
+    report::ChangeReport,
+};
+
+mod common;
+
+#[test]
+fn hooks_get_zcb_environment() {
+    let out = tempfile::tempdir().unwrap();
+    let env = out.path().join("env");
+    let (outdir, ctx) = context(&format!(
+        "[hooks]\npost_commit = \"env | grep ^ZCB_ | sort > {}\"",
+        env.display()
+    ));
+    let changes = ChangeReport {
+        added: vec!["web01".to_string()],
+        modified: vec!["dns".to_string(), "db01".to_string()],
+        ..Default::default()
+    };
+    let mut vars = change_vars(&changes);
+    vars.push(("ZCB_ARCHIVE", "/a/b.zones.tar.zst".to_string()));
+
+    run_hook(&ctx, Hook::PostCommit, &vars).unwrap();
+
+    assert_eq!(
+        fs::read_to_string(&env).unwrap(),
+        format!(
+            "ZCB_ADDED_ZONES=web01\n\
+             ZCB_ARCHIVE=/a/b.zones.tar.zst\n\
+             ZCB_CHANGED_ZONES=db01 dns web01\n\
+             ZCB_HOOK=post_commit\n\
+             ZCB_LATEST=\n\
+             ZCB_MODIFIED_ZONES=dns db01\n\
+             ZCB_OUTDIR={}\n\
+             ZCB_PREFIX=test\n\
+             ZCB_REMOVED_ZONES=\n",
+            outdir.path().display()
+        )
+    );
+
+    // hooks that aren't configured are skipped
+    run_hook(&ctx, Hook::PreBackup, &[]).unwrap();
+}
+
+#[test]
+fn failing_and_hanging_hooks_are_errors() {
+    let (_outdir, ctx) =
+        context("[hooks]\npre_backup = \"exit 3\"\npost_prune = \"sleep 5\"\ntimeout = \"200ms\"");
+
+    let err = run_hook(&ctx, Hook::PreBackup, &[]).unwrap_err();
+    assert!(err.to_string().contains("pre_backup hook failed"), "{err}");
+
+    let err = run_hook(&ctx, Hook::PostPrune, &[]).unwrap_err();
+    assert_eq!(err.to_string(), "post_prune hook timed out after 200ms");
+}
+
+#[test]
+fn timeout_kills_what_the_hook_started() {
+    let out = tempfile::tempdir().unwrap();
+    let marker = out.path().join("marker");
+    let (_outdir, ctx) = context(&format!(
+        "[hooks]\npost_prune = \"(sleep 1; touch {}) & wait\"\ntimeout = \"200ms\"",
+        marker.display()
+    ));
+
+    assert!(run_hook(&ctx, Hook::PostPrune, &[]).is_err());
+    thread::sleep(Duration::from_millis(1500));
+    assert!(!marker.exists());
+}
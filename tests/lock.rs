@@ -0,0 +1,23 @@
+use std::{fs, time::Duration};
+
+use zonecfg_backup::lock::{RunLock, LOCK_NAME};
+
+#[test]
+fn second_lock_names_holder_and_times_out() {
+    let outdir = tempfile::tempdir().unwrap();
+    let lock = RunLock::acquire(outdir.path(), Duration::ZERO).unwrap();
+    assert_eq!(
+        fs::read_to_string(outdir.path().join(LOCK_NAME)).unwrap(),
+        format!("{}\n", std::process::id())
+    );
+
+    let err = RunLock::acquire(outdir.path(), Duration::from_millis(250)).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains(&format!("is locked by pid {}", std::process::id())),
+        "{err}"
+    );
+
+    drop(lock);
+    RunLock::acquire(outdir.path(), Duration::ZERO).unwrap();
+}
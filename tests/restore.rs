@@ -0,0 +1,121 @@
+use std::fs;
+
+use common::{context, fixtures};
+use zonecfg_backup::{
+    resolve_snapshot,
+    restore::{restore_script, restore_zones, RestoreOptions},
+    snapshot_zone_configs,
+    source::DirectorySource,
+    try_commit_zone_snapshot,
+};
+
+mod common;
+
+#[test]
+fn restore_script_forces_create() {
+    let export = b"create -b\nset zonepath=/zones/dns\n";
+    assert_eq!(
+        restore_script(export, false).unwrap(),
+        "create -b\nset zonepath=/zones/dns\n"
+    );
+    assert_eq!(
+        restore_script(export, true).unwrap(),
+        "create -F -b\nset zonepath=/zones/dns\n"
+    );
+}
+
+#[test]
+fn restore_recreates_missing_zone() {
+    let (_outdir, ctx) = context("");
+    let snapshot = snapshot_zone_configs(&ctx, &DirectorySource::new(fixtures())).unwrap();
+    try_commit_zone_snapshot(&ctx, snapshot).unwrap();
+    let latest = resolve_snapshot(&ctx, None).unwrap();
+
+    let target = tempfile::tempdir().unwrap();
+    let source = DirectorySource::new(target.path());
+    let zones = vec!["db01".to_string()];
+    restore_zones(
+        &ctx,
+        &source,
+        &latest,
+        &zones,
+        RestoreOptions::default(),
+        Vec::new(),
+    )
+    .unwrap();
+    assert_eq!(
+        fs::read(target.path().join("db01.export")).unwrap(),
+        fs::read(fixtures().join("db01.export")).unwrap()
+    );
+
+    // the zone now exists, so a second restore must be forced
+    let err = restore_zones(
+        &ctx,
+        &source,
+        &latest,
+        &zones,
+        RestoreOptions::default(),
+        Vec::new(),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("db01"), "{err}");
+
+    let opts = RestoreOptions {
+        force: true,
+        dry_run: false,
+    };
+    restore_zones(&ctx, &source, &latest, &zones, opts, Vec::new()).unwrap();
+    let restored = fs::read_to_string(target.path().join("db01.export")).unwrap();
+    assert!(restored.starts_with("create -F -b\n"));
+}
+
+#[test]
+fn restore_dry_run_prints_script() {
+    let (_outdir, ctx) = context("");
+    let snapshot = snapshot_zone_configs(&ctx, &DirectorySource::new(fixtures())).unwrap();
+    try_commit_zone_snapshot(&ctx, snapshot).unwrap();
+    let latest = resolve_snapshot(&ctx, Some("latest")).unwrap();
+
+    let target = tempfile::tempdir().unwrap();
+    let opts = RestoreOptions {
+        force: false,
+        dry_run: true,
+    };
+    let mut out = Vec::new();
+    restore_zones(
+        &ctx,
+        &DirectorySource::new(target.path()),
+        &latest,
+        &["dns".to_string()],
+        opts,
+        &mut out,
+    )
+    .unwrap();
+
+    let expected = format!(
+        "/usr/sbin/zonecfg -z dns -f - <<'EOF'\n{}EOF\n",
+        fs::read_to_string(fixtures().join("dns.export")).unwrap()
+    );
+    assert_eq!(String::from_utf8(out).unwrap(), expected);
+    assert_eq!(fs::read_dir(target.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn restore_requires_export_member() {
+    let (_outdir, ctx) = context("capture = [\"info\"]");
+    let snapshot = snapshot_zone_configs(&ctx, &DirectorySource::new(fixtures())).unwrap();
+    try_commit_zone_snapshot(&ctx, snapshot).unwrap();
+    let latest = resolve_snapshot(&ctx, None).unwrap();
+
+    let target = tempfile::tempdir().unwrap();
+    let err = restore_zones(
+        &ctx,
+        &DirectorySource::new(target.path()),
+        &latest,
+        &["dns".to_string()],
+        RestoreOptions::default(),
+        Vec::new(),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("dns.export"), "{err}");
+}
@@ -0,0 +1,205 @@
+use std::{fs, os::unix::fs::symlink, time::Duration};
+
+use chrono::{DateTime, TimeZone, Utc};
+use common::{archives, context};
+use zonecfg_backup::{
+    file_latest, file_snapshot, find_prunable, prune_zonecfg_backups,
+    retention::{Keep, Limits, Retention},
+};
+
+mod common;
+
+fn at(y: i32, m: u32, d: u32, h: u32) -> Option<DateTime<Utc>> {
+    Some(Utc.with_ymd_and_hms(y, m, d, h, 0, 0).unwrap())
+}
+
+#[test]
+fn unlimited_without_any_counts() {
+    let times = [at(2022, 9, 10, 4), at(2022, 9, 10, 3)];
+    assert_eq!(
+        Retention::default().apply(&times),
+        [Some(Keep::Unlimited), Some(Keep::Unlimited)]
+    );
+}
+
+#[test]
+fn newest_backup_of_each_period_is_kept() {
+    // newest first, a burst of edits on one afternoon followed by older history
+    let times = [
+        at(2022, 9, 10, 16),
+        at(2022, 9, 10, 15),
+        at(2022, 9, 10, 14),
+        at(2022, 9, 9, 10),
+        at(2022, 9, 1, 10),
+        at(2022, 8, 20, 10),
+        at(2022, 7, 2, 10),
+        at(2021, 12, 30, 10),
+        None,
+    ];
+    let retention = Retention {
+        last: Some(2),
+        daily: Some(3),
+        monthly: Some(3),
+        yearly: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(
+        retention.apply(&times),
+        [
+            Some(Keep::Last),
+            Some(Keep::Last),
+            None,
+            Some(Keep::Daily),
+            Some(Keep::Daily),
+            Some(Keep::Monthly),
+            Some(Keep::Monthly),
+            Some(Keep::Yearly),
+            None,
+        ]
+    );
+}
+
+#[test]
+fn weeks_follow_iso_weeks() {
+    // 2022-01-02 is a Sunday and still in ISO week 52 of 2021
+    let times = [at(2022, 1, 3, 0), at(2022, 1, 2, 0), at(2022, 1, 1, 0)];
+    let retention = Retention {
+        weekly: Some(5),
+        ..Default::default()
+    };
+    assert_eq!(
+        retention.apply(&times),
+        [Some(Keep::Weekly), Some(Keep::Weekly), None]
+    );
+}
+
+#[test]
+fn prune_applies_gfs_retention() {
+    let (outdir, ctx) = context("keep_daily = 2");
+    // number_of_backups = 3 from the test config is an alias for keep_last
+    for ts in [
+        1662825600, // 2022-09-10 16:00
+        1662822000, // 2022-09-10 15:00
+        1662818400, // 2022-09-10 14:00
+        1662814800, // 2022-09-10 13:00
+        1662721200, // 2022-09-09 11:00
+        1662717600, // 2022-09-09 10:00
+        1662631200, // 2022-09-08 10:00
+    ] {
+        fs::write(file_snapshot(&ctx, ts), b"").unwrap();
+    }
+
+    prune_zonecfg_backups(&ctx).unwrap();
+
+    assert_eq!(
+        archives(outdir.path()),
+        [
+            "test_1662721200.zones.tar.zst",
+            "test_1662818400.zones.tar.zst",
+            "test_1662822000.zones.tar.zst",
+            "test_1662825600.zones.tar.zst",
+        ]
+    );
+}
+
+#[test]
+fn limits_overrule_retention_but_spare_latest() {
+    let now = Utc.with_ymd_and_hms(2022, 9, 10, 12, 0, 0).unwrap();
+    let backups = [
+        (at(2022, 9, 10, 11), 40),
+        (at(2022, 9, 10, 10), 40),
+        (at(2022, 9, 9, 10), 40),
+        (at(2022, 9, 1, 10), 10),
+        (at(2022, 8, 1, 10), 10),
+        (None, 10),
+    ];
+    let limits = Limits {
+        max_age: Some(Duration::from_secs(30 * 24 * 60 * 60)),
+        max_total_size: Some(100),
+    };
+
+    // latest is the second newest, so it counts first and the newest still fits
+    let mut keep = vec![Some(Keep::Unlimited); backups.len()];
+    limits.apply(now, &backups, Some(1), &mut keep);
+    assert_eq!(
+        keep,
+        [
+            Some(Keep::Unlimited),
+            Some(Keep::Latest),
+            None,
+            None,
+            None,
+            None,
+        ]
+    );
+
+    let limits = Limits {
+        max_total_size: None,
+        ..limits
+    };
+    let mut keep = vec![Some(Keep::Unlimited); backups.len()];
+    limits.apply(now, &backups, None, &mut keep);
+    assert_eq!(
+        keep,
+        [
+            Some(Keep::Unlimited),
+            Some(Keep::Unlimited),
+            Some(Keep::Unlimited),
+            Some(Keep::Unlimited),
+            None,
+            Some(Keep::Unlimited),
+        ]
+    );
+}
+
+#[test]
+fn limits_are_parsed_from_config() {
+    let (_outdir, ctx) = context("max_age = \"180d\"\nmax_total_size = \"2G\"");
+    assert_eq!(
+        Limits::from_config(&ctx.config).unwrap(),
+        Limits {
+            max_age: Some(Duration::from_secs(180 * 24 * 60 * 60)),
+            max_total_size: Some(2 << 30),
+        }
+    );
+
+    let (_outdir, ctx) = context("max_total_size = \"2 furlongs\"");
+    assert!(Limits::from_config(&ctx.config).is_err());
+}
+
+#[test]
+fn prune_never_removes_latest() {
+    let (outdir, ctx) = context("max_age = \"1d\"");
+    for ts in [1662780550, 1662780551] {
+        fs::write(file_snapshot(&ctx, ts), b"").unwrap();
+    }
+    symlink(file_snapshot(&ctx, 1662780550), file_latest(&ctx)).unwrap();
+
+    prune_zonecfg_backups(&ctx).unwrap();
+
+    assert_eq!(archives(outdir.path()), ["test_1662780550.zones.tar.zst"]);
+}
+
+#[test]
+fn prunable_accounts_for_pending_backup() {
+    let (outdir, ctx) = context("");
+    for ts in 1662780550..1662780554 {
+        fs::write(file_snapshot(&ctx, ts), b"").unwrap();
+    }
+
+    assert_eq!(
+        find_prunable(&ctx, None).unwrap(),
+        [file_snapshot(&ctx, 1662780550)]
+    );
+    let pending = file_snapshot(&ctx, 1662780560);
+    assert_eq!(
+        find_prunable(&ctx, Some((&pending, 0))).unwrap(),
+        [
+            file_snapshot(&ctx, 1662780551),
+            file_snapshot(&ctx, 1662780550),
+        ]
+    );
+    assert!(find_prunable(&ctx, Some((&outdir.path().join("other"), 0))).is_err());
+    // planning leaves outdir alone
+    assert_eq!(archives(outdir.path()).len(), 4);
+}
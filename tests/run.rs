@@ -0,0 +1,128 @@
+use std::{fs, path::Path, time::Duration};
+
+use anyhow::anyhow;
+use common::{archives, fixtures};
+use serde_json::{json, Value};
+use slog::{o, Logger};
+use zonecfg_backup::{
+    config::Config,
+    lock::RunLock,
+    run::{self, Destination},
+    source::DirectorySource,
+};
+
+mod common;
+
+fn destinations(toml: &str) -> Vec<Destination> {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("config.toml");
+    fs::write(&path, toml).unwrap();
+    let config = Config::from_file(path).unwrap();
+    run::destinations(&config, &Logger::root(slog::Discard, o!()), None).unwrap()
+}
+
+/// Run a backup of the fixture zones, returning its result and JSON report.
+fn backup(destinations: &[Destination]) -> (Result<(), anyhow::Error>, Option<Value>) {
+    let mut report = None;
+    let result = run::backup(destinations, &DirectorySource::new(fixtures()), |commits| {
+        report = Some(serde_json::to_value(commits)?);
+        Ok(())
+    });
+    (result, report)
+}
+
+fn touch(path: &Path) -> String {
+    format!("touch {}", path.display())
+}
+
+#[test]
+fn failing_post_commit_still_prunes() {
+    let outdir = tempfile::tempdir().unwrap();
+    let error = outdir.path().join("error");
+    let destinations = destinations(&format!(
+        "outdir = {:?}\nprefix = \"test\"\nkeep_last = 2\n\
+         [hooks]\npost_commit = \"exit 1\"\non_error = \"echo \\\"$ZCB_ERROR\\\" > {}\"\n",
+        outdir.path(),
+        error.display()
+    ));
+    for ts in 1662780550..1662780553 {
+        fs::write(outdir.path().join(format!("test_{ts}.zones.tar.zst")), b"").unwrap();
+    }
+
+    let (result, report) = backup(&destinations);
+
+    let err = result.unwrap_err();
+    assert!(err.to_string().contains("post_commit hook failed"), "{err}");
+    assert!(fs::read_to_string(&error)
+        .unwrap()
+        .contains("post_commit hook failed"));
+    let written = archives(outdir.path());
+    assert_eq!(written.len(), 2);
+    assert_eq!(written[0], "test_1662780552.zones.tar.zst");
+    // a single destination reports its commit alone
+    let report = report.unwrap();
+    assert_eq!(report["changes"]["added"], json!(["db01", "dns", "web01"]));
+    assert!(report.get("destination").is_none());
+}
+
+#[test]
+fn unlockable_destination_does_not_stop_the_others() {
+    let good = tempfile::tempdir().unwrap();
+    let destinations = destinations(&format!(
+        "prefix = \"test\"\n\
+         [[destination]]\nname = \"missing\"\noutdir = \"/nonexistent/zonecfg-backup\"\n\
+         [[destination]]\nname = \"good\"\noutdir = {:?}\n",
+        good.path()
+    ));
+
+    let (result, report) = backup(&destinations);
+
+    assert_eq!(
+        result.unwrap_err().to_string(),
+        "1 of 2 destinations failed"
+    );
+    assert_eq!(archives(good.path()).len(), 1);
+    // with several destinations the report is an array, even with one entry
+    let report = report.unwrap();
+    let report = report.as_array().unwrap();
+    assert_eq!(report.len(), 1);
+    assert_eq!(report[0]["destination"], "good");
+    assert!(report[0]["archive"].is_string());
+}
+
+#[test]
+fn pre_backup_waits_for_the_lock() {
+    let outdir = tempfile::tempdir().unwrap();
+    let started = outdir.path().join("started");
+    let destinations = destinations(&format!(
+        "outdir = {:?}\nlock_timeout = \"0s\"\n[hooks]\npre_backup = \"{}\"\n",
+        outdir.path(),
+        touch(&started)
+    ));
+    let _lock = RunLock::acquire(outdir.path(), Duration::ZERO).unwrap();
+
+    let (result, report) = backup(&destinations);
+
+    let err = result.unwrap_err();
+    assert!(err.to_string().contains("is locked by"), "{err}");
+    assert!(!started.exists());
+    assert!(report.is_none());
+}
+
+#[test]
+fn failing_report_still_runs_hooks() {
+    let outdir = tempfile::tempdir().unwrap();
+    let committed = outdir.path().join("committed");
+    let destinations = destinations(&format!(
+        "outdir = {:?}\n[hooks]\npost_commit = \"{}\"\n",
+        outdir.path(),
+        touch(&committed)
+    ));
+
+    let result = run::backup(&destinations, &DirectorySource::new(fixtures()), |_| {
+        Err(anyhow!("report went nowhere"))
+    });
+
+    assert_eq!(result.unwrap_err().to_string(), "report went nowhere");
+    assert!(committed.exists());
+}
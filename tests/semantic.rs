@@ -0,0 +1,71 @@
+use std::fs;
+
+use common::fixtures;
+use zonecfg_backup::{
+    semantic::{diff_configs, Change},
+    zonecfg::ZoneConfig,
+};
+
+mod common;
+
+fn db01() -> String {
+    fs::read_to_string(fixtures().join("db01.zone")).unwrap()
+}
+
+fn diff(old: &str, new: &str) -> Vec<String> {
+    let old = ZoneConfig::parse(old.as_bytes()).unwrap();
+    let new = ZoneConfig::parse(new.as_bytes()).unwrap();
+    diff_configs(&old, &new)
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+#[test]
+fn reordered_resources_are_not_changes() {
+    let original = db01();
+    // move the fs resource to the end
+    let start = original.find("fs:\n").unwrap();
+    let end = original.find("net:\n").unwrap();
+    let fs_resource = &original[start..end];
+    let reordered = original.replacen(fs_resource, "", 1) + fs_resource;
+
+    assert!(diff(&original, &reordered).is_empty());
+}
+
+#[test]
+fn reports_property_and_resource_changes() {
+    let original = db01();
+    let start = original.find("fs:\n").unwrap();
+    let end = original.find("net:\n").unwrap();
+    let edited = original
+        .replace("autoboot: true", "autoboot: false")
+        .replace("10.0.0.21/24", "10.0.0.22/24")
+        .replace("\t[swap: 8G]\n", "")
+        .replacen(&original[start..end], "", 1)
+        + "attr:\n\tname: owner\n\ttype: string\n\tvalue: ops\n";
+
+    assert_eq!(
+        diff(&original, &edited),
+        [
+            "autoboot changed true → false",
+            "attr[name=owner] added",
+            "capped-memory.swap removed (was 8G)",
+            "fs[dir=/data] removed",
+            "net[physical=db01].allowed-address changed 10.0.0.21/24 → 10.0.0.22/24",
+        ]
+    );
+}
+
+#[test]
+fn changes_serialize_with_their_kind() {
+    let change = Change::Changed {
+        path: "autoboot".to_string(),
+        from: "true".to_string(),
+        to: "false".to_string(),
+    };
+    assert_eq!(
+        serde_json::to_string(&change).unwrap(),
+        r#"{"change":"changed","path":"autoboot","from":"true","to":"false"}"#
+    );
+}
@@ -0,0 +1,127 @@
+use std::{fs, os::unix::fs::symlink};
+
+use common::{context, fixtures, read_archive};
+use zonecfg_backup::{
+    file_latest, file_sidecar, file_snapshot, generate_hash, prune_zonecfg_backups,
+    snapshot_zone_configs,
+    source::DirectorySource,
+    try_commit_zone_snapshot,
+    verify::{verify_archive, verify_latest, Verified},
+};
+
+mod common;
+
+#[test]
+fn commit_writes_checksum_sidecar() {
+    let (_outdir, ctx) = context("");
+    let snapshot = snapshot_zone_configs(&ctx, &DirectorySource::new(fixtures())).unwrap();
+    let path = try_commit_zone_snapshot(&ctx, snapshot)
+        .unwrap()
+        .archive
+        .unwrap();
+
+    let hash = generate_hash(fs::File::open(&path).unwrap()).unwrap();
+    let name = path.file_name().unwrap().to_str().unwrap();
+    assert_eq!(
+        fs::read_to_string(file_sidecar(&path)).unwrap(),
+        format!("{hash}  {name}\n")
+    );
+    assert_eq!(
+        verify_archive(&path).unwrap(),
+        Verified {
+            members: 7,
+            checksum: true,
+            manifest: true,
+        }
+    );
+    assert_eq!(verify_latest(&ctx).unwrap(), path);
+}
+
+#[test]
+fn verify_detects_damaged_archives() {
+    let (_outdir, ctx) = context("");
+    let snapshot = snapshot_zone_configs(&ctx, &DirectorySource::new(fixtures())).unwrap();
+    let path = try_commit_zone_snapshot(&ctx, snapshot)
+        .unwrap()
+        .archive
+        .unwrap();
+    let data = fs::read(&path).unwrap();
+
+    fs::write(&path, &data[..data.len() / 2]).unwrap();
+    let err = verify_archive(&path).unwrap_err();
+    assert!(format!("{err:#}").contains("does not match"), "{err:#}");
+
+    // without the sidecar, a truncated archive is caught while decoding
+    fs::remove_file(file_sidecar(&path)).unwrap();
+    assert!(verify_archive(&path).is_err());
+}
+
+#[test]
+fn verify_checks_members_against_manifest() {
+    let (outdir, ctx) = context("");
+    let snapshot = snapshot_zone_configs(&ctx, &DirectorySource::new(fixtures())).unwrap();
+    let mut members = read_archive(snapshot.file.path());
+    members.insert("dns.zone".to_string(), b"zonename: dns\n".to_vec());
+
+    // repack with the original manifest but an edited dns.zone
+    let path = outdir.path().join("test_1662780550.zones.tar.zst");
+    let encoder = zstd::Encoder::new(fs::File::create(&path).unwrap(), 3).unwrap();
+    let mut builder = tar::Builder::new(encoder.auto_finish());
+    for (name, data) in &members {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, name, &data[..]).unwrap();
+    }
+    builder.into_inner().unwrap();
+
+    let err = verify_archive(&path).unwrap_err();
+    assert_eq!(err.to_string(), "dns.zone does not match the manifest");
+}
+
+#[test]
+fn verify_latest_rejects_dangling_link() {
+    let (outdir, ctx) = context("");
+    assert!(verify_latest(&ctx).is_err());
+
+    symlink(file_snapshot(&ctx, 1662780550), file_latest(&ctx)).unwrap();
+    let err = verify_latest(&ctx).unwrap_err();
+    assert!(err.to_string().contains("missing backup"), "{err}");
+
+    // a file that isn't named like one of our backups doesn't count
+    let other = outdir.path().join("other_1662780550.zones.tar.zst");
+    fs::write(&other, b"").unwrap();
+    fs::remove_file(file_latest(&ctx)).unwrap();
+    symlink(&other, file_latest(&ctx)).unwrap();
+    let err = verify_latest(&ctx).unwrap_err();
+    assert!(err.to_string().contains("not a backup"), "{err}");
+}
+
+#[test]
+fn prune_removes_sidecars() {
+    let (outdir, ctx) = context("");
+    for ts in 1662780550..1662780555 {
+        let path = file_snapshot(&ctx, ts);
+        fs::write(&path, b"").unwrap();
+        fs::write(file_sidecar(&path), b"").unwrap();
+    }
+
+    prune_zonecfg_backups(&ctx).unwrap();
+
+    let mut names: Vec<String> = fs::read_dir(outdir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        [
+            "test_1662780552.zones.tar.zst",
+            "test_1662780552.zones.tar.zst.sha256",
+            "test_1662780553.zones.tar.zst",
+            "test_1662780553.zones.tar.zst.sha256",
+            "test_1662780554.zones.tar.zst",
+            "test_1662780554.zones.tar.zst.sha256",
+        ]
+    );
+}
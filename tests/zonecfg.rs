@@ -0,0 +1,94 @@
+use std::fs;
+
+use common::fixtures;
+use zonecfg_backup::zonecfg::{ResourceKind, Value, ZoneConfig};
+
+mod common;
+
+fn parse_fixture(zone: &str) -> ZoneConfig {
+    let info = fs::read(fixtures().join(format!("{zone}.zone"))).unwrap();
+    ZoneConfig::parse(&info).unwrap()
+}
+
+#[test]
+fn parses_global_properties() {
+    let db01 = parse_fixture("db01");
+    assert_eq!(db01.zonename(), Some("db01"));
+    assert_eq!(db01.zonepath(), Some("/zones/db01"));
+    assert_eq!(db01.brand(), Some("lipkg"));
+    assert_eq!(db01.autoboot(), Some(true));
+    assert_eq!(db01.ip_type(), Some("exclusive"));
+    assert_eq!(db01.get("scheduling-class"), Some("FSS"));
+    assert_eq!(db01.get("max-lwps"), Some("4000"));
+    // unset properties are left out
+    assert_eq!(db01.get("bootargs"), None);
+}
+
+#[test]
+fn parses_resources() {
+    let db01 = parse_fixture("db01");
+    let kinds: Vec<_> = db01.resources.iter().map(|r| r.kind.as_str()).collect();
+    assert_eq!(
+        kinds,
+        [
+            "fs",
+            "net",
+            "dataset",
+            "capped-memory",
+            "rctl",
+            "rctl",
+            "attr"
+        ]
+    );
+
+    let fs = db01.resources(&ResourceKind::Fs).next().unwrap();
+    assert_eq!(fs.key(), "fs[dir=/data]");
+    assert_eq!(
+        fs.get("options"),
+        Some(&Value::List(vec!["nodevices".to_string()]))
+    );
+    assert_eq!(fs.get("raw"), None);
+
+    let net = db01.resources(&ResourceKind::Net).next().unwrap();
+    assert_eq!(net.key(), "net[physical=db01]");
+    assert_eq!(
+        net.get("allowed-address"),
+        Some(&Value::Single("10.0.0.21/24".to_string()))
+    );
+
+    let mem = db01.resources(&ResourceKind::CappedMemory).next().unwrap();
+    assert_eq!(mem.key(), "capped-memory");
+    assert_eq!(mem.get("swap"), Some(&Value::Single("8G".to_string())));
+
+    let rctls: Vec<_> = db01
+        .resources(&ResourceKind::Rctl)
+        .map(|r| r.key())
+        .collect();
+    assert_eq!(
+        rctls,
+        ["rctl[name=zone.cpu-shares]", "rctl[name=zone.max-lwps]"]
+    );
+}
+
+#[test]
+fn repeated_properties_become_lists() {
+    let info = b"zonename: z\nrctl:\n\tname: zone.max-lwps\n\tvalue: (priv=privileged,limit=100,action=deny)\n\tvalue: (priv=basic,limit=50,action=deny)\n";
+    let z = ZoneConfig::parse(info).unwrap();
+    assert_eq!(
+        z.resources[0].get("value"),
+        Some(&Value::List(vec![
+            "(priv=privileged,limit=100,action=deny)".to_string(),
+            "(priv=basic,limit=50,action=deny)".to_string(),
+        ]))
+    );
+}
+
+#[test]
+fn errors_carry_line_numbers() {
+    let err = ZoneConfig::parse(b"zonename: z\n\tphysical: vnic0\n").unwrap_err();
+    assert_eq!(err.line, 2);
+
+    let err = ZoneConfig::parse(b"zonename: z\nnet:\n\tphysical vnic0\n").unwrap_err();
+    assert_eq!(err.line, 3);
+    assert!(err.to_string().starts_with("line 3: "), "{err}");
+}